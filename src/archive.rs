@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use crate::parser::{path_key, ProjectParser};
+
+pub const ARCHIVE_EXTENSION: &str = "hhe";
+const MANIFEST: &str = "manifest.json";
+
+/// Written into every archive. `checksum` covers every file, so saves can
+/// tell when the story they were made with has changed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Manifest {
+    pub name: String,
+    pub engine_version: String,
+    /// SHA-256 of each file, keyed by its path inside the archive
+    pub files: BTreeMap<String, String>,
+    pub checksum: String,
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+fn checksum(hashes: &BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
+    for (path, hash) in hashes {
+        hasher.update(path.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(hash.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+fn hashes(files: &BTreeMap<String, Vec<u8>>) -> BTreeMap<String, String> {
+    files
+        .iter()
+        .map(|(path, bytes)| (path.clone(), sha256(bytes)))
+        .collect()
+}
+
+fn is_archive(path: &Path) -> bool {
+    path.extension().is_some_and(|v| v == ARCHIVE_EXTENSION)
+}
+
+/// The `/` separated path, relative to `root`, of every file under `dir`,
+/// skipping hidden files and other archives. Nothing is read.
+fn collect_paths(root: &Path, dir: &Path, paths: &mut BTreeSet<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|v| v.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect_paths(root, &path, paths)?;
+        } else if !is_archive(&path) {
+            let relative: Vec<String> = path
+                .strip_prefix(root)?
+                .components()
+                .map(|v| v.as_os_str().to_string_lossy().to_string())
+                .collect();
+            paths.insert(relative.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// Every file under `root` keyed by its path from [`collect_paths`].
+fn collect_files(root: &Path, files: &mut BTreeMap<String, Vec<u8>>) -> Result<()> {
+    let mut paths = BTreeSet::new();
+    collect_paths(root, root, &mut paths)?;
+    for path in paths {
+        let bytes = fs::read(root.join(&path))?;
+        files.insert(path, bytes);
+    }
+    Ok(())
+}
+
+/// A packed story, read fully into memory.
+#[derive(Debug, Clone)]
+pub struct Archive {
+    pub manifest: Manifest,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Archive> {
+        let mut zip = ZipArchive::new(Cursor::new(fs::read(path)?))?;
+        let mut files = BTreeMap::new();
+        let mut manifest = None;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)?;
+            if file.name() == MANIFEST {
+                manifest = Some(serde_json::from_slice::<Manifest>(&bytes)?);
+            } else {
+                files.insert(file.name().to_string(), bytes);
+            }
+        }
+        let manifest = manifest.ok_or(anyhow!("{} has no {}", path.display(), MANIFEST))?;
+
+        if hashes(&files) != manifest.files || checksum(&manifest.files) != manifest.checksum {
+            return Err(anyhow!(
+                "{} doesn't match its manifest, it may be corrupt",
+                path.display()
+            ));
+        }
+        Ok(Archive { manifest, files })
+    }
+}
+
+/// Where a project's files are read from.
+#[derive(Debug, Clone)]
+pub enum ProjectSource {
+    Folder(PathBuf),
+    Archive(Archive),
+}
+
+impl ProjectSource {
+    /// Opens `path` as an archive if it ends in `.hhe`, otherwise as a folder.
+    pub fn open(path: &Path) -> Result<ProjectSource> {
+        if path.is_file() && is_archive(path) {
+            Ok(ProjectSource::Archive(Archive::open(path)?))
+        } else {
+            Ok(ProjectSource::Folder(path.to_path_buf()))
+        }
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> Result<String> {
+        match self {
+            ProjectSource::Folder(root) => Ok(fs::read_to_string(root.join(path))?),
+            ProjectSource::Archive(archive) => {
+                let bytes = archive
+                    .files
+                    .get(&path_key(path))
+                    .ok_or(anyhow!("{} isn't in the archive", path.display()))?;
+                Ok(String::from_utf8(bytes.clone())?)
+            }
+        }
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        match self {
+            ProjectSource::Folder(root) => root.join(path).is_dir(),
+            ProjectSource::Archive(archive) => {
+                let prefix = format!("{}/", path_key(path));
+                archive.files.keys().any(|v| v.starts_with(&prefix))
+            }
+        }
+    }
+
+    /// Every file below `dir`, at any depth, relative to the project root.
+    pub fn files_under(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        match self {
+            ProjectSource::Folder(root) => {
+                let mut paths = BTreeSet::new();
+                collect_paths(root, &root.join(dir), &mut paths)?;
+                Ok(paths.into_iter().map(PathBuf::from).collect())
+            }
+            ProjectSource::Archive(archive) => {
+                let prefix = format!("{}/", path_key(dir));
+                Ok(archive
+                    .files
+                    .keys()
+                    .filter(|v| v.starts_with(&prefix))
+                    .map(PathBuf::from)
+                    .collect())
+            }
+        }
+    }
+
+    /// Checksum over the project's files, matching the one `pack` writes.
+    /// Folders are read and hashed every time, so this is only worked out when
+    /// a save needs it.
+    pub fn checksum(&self) -> Result<String> {
+        match self {
+            ProjectSource::Folder(root) => {
+                let mut files = BTreeMap::new();
+                collect_files(root, &mut files)?;
+                Ok(checksum(&hashes(&files)))
+            }
+            ProjectSource::Archive(archive) => Ok(archive.manifest.checksum.clone()),
+        }
+    }
+}
+
+/// Packs the project folder at `project_root` into a single `.hhe` archive.
+/// The project is parsed first so broken stories aren't shared.
+pub fn pack(project_root: &str, output: &Path) -> Result<Manifest> {
+    let project = ProjectParser::new(project_root).parse()?;
+    let root = fs::canonicalize(project_root)?;
+    let mut files = BTreeMap::new();
+    collect_files(&root, &mut files)?;
+
+    let files_hashes = hashes(&files);
+    let manifest = Manifest {
+        name: project.name,
+        engine_version: env!("CARGO_PKG_VERSION").to_string(),
+        checksum: checksum(&files_hashes),
+        files: files_hashes,
+    };
+
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default();
+    zip.start_file(MANIFEST, options)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    for (path, bytes) in &files {
+        zip.start_file(path.as_str(), options)?;
+        zip.write_all(bytes)?;
+    }
+    zip.finish()?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_and_archive_agree() {
+        let root = std::env::temp_dir().join(format!("hhe3-pack-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("rooms/ch1")).unwrap();
+        fs::write(
+            root.join("meta.toml"),
+            "[settings]\nfirst_room = \"index\"\nrooms_folder = [\"./rooms\"]\n[meta]\nname = \"t\"\n",
+        )
+        .unwrap();
+        fs::write(root.join("rooms/index.hh3"), "--- content\nhi\n").unwrap();
+        fs::write(root.join("rooms/ch1/a.hh3"), "--- content\na\n").unwrap();
+        fs::write(root.join("rooms/.hidden"), "").unwrap();
+
+        let folder = ProjectSource::open(&root).unwrap();
+        let rooms = folder.files_under(Path::new("rooms")).unwrap();
+        assert_eq!(
+            rooms,
+            [
+                PathBuf::from("rooms/ch1/a.hh3"),
+                PathBuf::from("rooms/index.hh3")
+            ]
+        );
+
+        let output = root.with_extension(ARCHIVE_EXTENSION);
+        let manifest = pack(root.to_str().unwrap(), &output).unwrap();
+        let archive = ProjectSource::open(&output).unwrap();
+        assert_eq!(folder.checksum().unwrap(), manifest.checksum);
+        assert_eq!(archive.checksum().unwrap(), manifest.checksum);
+        assert_eq!(archive.files_under(Path::new("rooms")).unwrap(), rooms);
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::Stylize,
+    symbols::border,
+    text::Line,
+    widgets::{Block, Clear, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::environment::Environment;
+
+/// Developer console drawn over the story, evaluating lisp in the live
+/// environment. Output goes to the debug log so it shows alongside `debug`.
+#[derive(Debug, Default)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    scroll: usize,
+}
+
+impl Console {
+    /// Handles `ev` if it belongs to the console, returning whether it was used.
+    pub fn input(&mut self, ev: &Event, environment: &mut Environment) -> bool {
+        let Event::Key(KeyEvent {
+            code,
+            kind: KeyEventKind::Press,
+            ..
+        }) = ev
+        else {
+            return self.open;
+        };
+
+        if !self.open {
+            if *code == KeyCode::Char('`') {
+                self.open = true;
+                return true;
+            }
+            return false;
+        }
+
+        match code {
+            KeyCode::Char('`') | KeyCode::Esc => self.open = false,
+            KeyCode::Char(c) => self.input.push(*c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Up => {
+                let i = match self.history_index {
+                    Some(i) => i.saturating_sub(1),
+                    None => self.history.len().saturating_sub(1),
+                };
+                if let Some(line) = self.history.get(i) {
+                    self.history_index = Some(i);
+                    self.input = line.clone();
+                }
+            }
+            KeyCode::Down => match self.history_index {
+                Some(i) if i + 1 < self.history.len() => {
+                    self.history_index = Some(i + 1);
+                    self.input = self.history[i + 1].clone();
+                }
+                _ => {
+                    self.history_index = None;
+                    self.input.clear();
+                }
+            },
+            KeyCode::PageUp => self.scroll += 1,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut self.input);
+                if !line.trim().is_empty() {
+                    self.history.push(line.clone());
+                    self.run(&line, environment);
+                }
+                self.history_index = None;
+                self.scroll = 0;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn run(&mut self, line: &str, environment: &mut Environment) {
+        environment
+            .data
+            .write()
+            .unwrap()
+            .debug
+            .push(format!("> {}", line));
+        let result = match line.strip_prefix(':') {
+            Some(command) => Self::command(command, environment),
+            None => environment.eval_source(line).map(|v| format!("=> {}", v)),
+        };
+        let mut data = environment.data.write().unwrap();
+        match result {
+            Ok(v) => data.debug.push(v),
+            Err(e) => data.debug.push(format!("error: {}", e)),
+        }
+    }
+
+    /// Console commands, written with a leading `:`.
+    fn command(command: &str, environment: &mut Environment) -> Result<String> {
+        let mut words = command.split_whitespace();
+        match words.next() {
+            // :goto <room> [name=value]... jumps to a room, then sets variables
+            Some("goto") => {
+                let room = words
+                    .next()
+                    .ok_or(anyhow!("usage: :goto <room> [name=value]..."))?;
+                let assignments: Vec<String> = words.map(|v| v.to_string()).collect();
+                let variables = environment.eval_assignments(&assignments)?;
+                environment.enter_room(room)?;
+                for (name, value) in variables {
+                    environment.bind(&name, value);
+                }
+                Ok(format!("=> entered {}", room))
+            }
+            _ => Err(anyhow!("unknown command :{}, try :goto", command)),
+        }
+    }
+
+    pub fn render(&self, frame: &mut Frame, environment: &Environment) {
+        let area = frame.area();
+        let area = Rect {
+            height: (area.height * 2 / 3).max(3),
+            ..area
+        };
+        let block = Block::bordered()
+            .border_set(border::DOUBLE)
+            .title("Console")
+            .title_bottom("` to close, PgUp/PgDn to scroll");
+        let inner = block.inner(area);
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+            .split(inner);
+
+        let data = environment.data.read().unwrap();
+        let height = layout[0].height as usize;
+        let end = data.debug.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        let lines: Vec<Line> = data.debug[start..end]
+            .iter()
+            .map(|v| {
+                if v.starts_with("error: ") {
+                    Line::from(v.as_str()).red()
+                } else {
+                    Line::from(v.as_str())
+                }
+            })
+            .collect();
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+        frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), layout[0]);
+        frame.render_widget(
+            Paragraph::new(format!("> {}_", self.input)).bold(),
+            layout[1],
+        );
+    }
+}
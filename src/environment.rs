@@ -1,10 +1,11 @@
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     rc::Rc,
     sync::{Arc, RwLock},
 };
 
+use anyhow::Result;
 use ratatui::{
     style::Stylize,
     text::{Line, Span, Text},
@@ -13,14 +14,17 @@ use ratatui::{
 use rust_lisp::{
     default_env,
     interpreter::eval,
-    model::{Env, Symbol, Value},
+    lisp,
+    model::{Env, RuntimeError, Symbol, Value},
     parser::parse,
-    utils::{require_arg, require_typed_arg},
+    utils::require_arg,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    lisp::{self, color::Color},
-    project::{Project, Room},
+    errors::HHEError,
+    lisp::{self, character::Character, color::Color, style::Style},
+    project::{Markup, Project, Room, RoomMeta},
 };
 
 pub struct Environment {
@@ -29,139 +33,592 @@ pub struct Environment {
     tick_passed: i64,
     pub prev_time: i64,
     pub prev_room: String,
+    /// Names defined by lisp evaluated through [`Environment::eval_source`]
+    defined: BTreeSet<String>,
+    /// How much faster than the story's delays content is typed, from the
+    /// player's settings
+    pub speed: f64,
 }
 
 pub type Container = Arc<RwLock<EnvData>>;
 
+/// What the [`Container`] is bound to in the lisp environment, for builtins
+/// that are plain functions. The space keeps stories from using it.
+const CONTAINER_SYMBOL: &str = "hhe3 data";
+
+/// The [`Container`] of the environment that `env` belongs to, for builtins
+/// registered as [`Value::NativeFunc`] instead of with `insert_func!`.
+pub fn container(env: &Rc<RefCell<Env>>) -> Result<Container, RuntimeError> {
+    match env.borrow().get(&Symbol::from(CONTAINER_SYMBOL)) {
+        Some(Value::Foreign(v)) if v.is::<Container>() => {
+            Ok(Arc::clone(v.downcast_ref::<Container>().unwrap()))
+        }
+        _ => Err(RuntimeError {
+            msg: "The engine builtins aren't registered".to_string(),
+        }),
+    }
+}
+
 impl Environment {
-    pub fn update(&mut self) {
+    pub fn update(&mut self) -> Result<()> {
         let now = chrono::offset::Utc::now().timestamp_millis();
         let dt = now - self.prev_time;
+        self.prev_time = now;
+        self.step(dt)
+    }
+
+    /// Advances the story by `dt` milliseconds, loading the room if it changed,
+    /// moving colour tweens along and revealing as much content as the delay
+    /// allows.
+    pub fn step(&mut self, dt: i64) -> Result<()> {
         let mut content_ticks = 0;
+        {
+            let mut data = self.data.write().unwrap();
+            data.step_tweens(dt);
+            data.display.clock += (dt.max(0) as f64 * self.speed).round() as u64;
+        }
 
         let cur_room = {
             let read: std::sync::RwLockReadGuard<'_, EnvData> = self.data.read().unwrap();
             read.current_room.clone()
         };
         if self.prev_room != cur_room {
-            self.load_room(&cur_room);
-            self.prev_room = cur_room;
+            self.change_room(&cur_room)?;
         }
 
-        if self.prev_room != "" {
-            let this_room = self.current_room();
+        if !self.prev_room.is_empty() {
+            let this_room = self.current_room()?;
             {
+                let too_far = self.more_to_type(&this_room);
                 let data = Arc::clone(&self.data);
-                let data = data.read().unwrap();
+                let mut data = data.write().unwrap();
 
-                let too_far = data.display.displayed_index < this_room.content.len();
-                if data.display.delay != 0 && too_far {
-                    self.tick_passed += dt;
-                    content_ticks = self.tick_passed / data.display.delay;
-                    self.tick_passed %= data.display.delay;
-                };
+                // time spent in `(wait ms)` doesn't count towards typing
+                let waited = dt.min(data.display.wait_ms);
+                data.display.wait_ms -= waited;
+                let dt = dt - waited;
+
+                if too_far && !data.display.waiting() {
+                    if data.display.reveal == RevealMode::Instant {
+                        content_ticks = 1;
+                    } else if data.display.delay != 0 {
+                        let delay = data.display.scaled_delay(self.speed);
+                        self.tick_passed += dt;
+                        content_ticks = self.tick_passed / delay;
+                        self.tick_passed %= delay;
+                    }
+                }
             }
         }
         for _ in 0..content_ticks {
-            self.tick_content();
+            self.reveal_step()?;
+            if self.data.read().unwrap().display.waiting() {
+                self.tick_passed = 0;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets the content carry on after a `(wait-key)`. Returns whether it was
+    /// waiting, so the key isn't used for anything else.
+    pub fn continue_reading(&mut self) -> bool {
+        let mut data = self.data.write().unwrap();
+        if std::mem::take(&mut data.display.clear_on_continue) {
+            data.display.clear();
+            data.display.scroll = 0;
+            data.update_more_pages();
+        }
+        std::mem::take(&mut data.display.wait_key)
+    }
+
+    /// Puts `error` in the debug log, for frontends that keep running after one.
+    pub fn report(&self, error: anyhow::Error) {
+        self.data
+            .write()
+            .unwrap()
+            .debug
+            .push(format!("error: {}", error));
+    }
+
+    /// Runs the action of the option at `index`, as if the player had selected it.
+    /// Options are hidden until the last page, see [`EnvData::more_pages`].
+    pub fn select_option(&mut self, index: usize) -> Result<()> {
+        let option = {
+            let read = self.data.read().unwrap();
+            if read.more_pages() {
+                return Err(HHEError::OptionOutOfRange(index).into());
+            }
+            match read.options.options.get(index) {
+                Some(v) => v.clone(),
+                None => return Err(HHEError::OptionOutOfRange(index).into()),
+            }
+        };
+
+        eval(
+            Rc::clone(&self.context),
+            &lisp! {
+                ( {option.action} )
+            },
+        )
+        .map_err(|e| HHEError::Lisp(e.msg))?;
+        Ok(())
+    }
+
+    /// Calls every `listener/keyboard/char` callback with `c`.
+    pub fn key_char(&mut self, c: char) -> Result<()> {
+        let listeners = self.data.read().unwrap().listeners.clone();
+        for (_, cb) in listeners.keyboard_char {
+            eval(
+                Rc::clone(&self.context),
+                &lisp! {
+                    ( {cb} {Value::String(c.to_string())} )
+                },
+            )
+            .map_err(|e| HHEError::Lisp(e.msg))?;
         }
+        Ok(())
+    }
 
-        self.prev_time = chrono::offset::Utc::now().timestamp_millis();
+    /// Loads `room` straight away instead of waiting for the next update.
+    /// `room` is resolved relative to the current room, see `Project::resolve_room`.
+    pub fn enter_room(&mut self, room: &str) -> Result<()> {
+        let room = {
+            let mut data = self.data.write().unwrap();
+            data.current_room = data.project.resolve_room(&data.current_room, room);
+            data.current_room.clone()
+        };
+        self.change_room(&room)
+    }
+
+    /// Runs the exit section of the room being left, then loads `room`.
+    fn change_room(&mut self, room: &str) -> Result<()> {
+        let leaving = std::mem::replace(&mut self.prev_room, room.to_string());
+        let exit = match self.data.read().unwrap().project.rooms.get(&leaving) {
+            Some(v) => v.exit.clone(),
+            None => String::new(),
+        };
+        let exited = self
+            .eval_source(&exit)
+            .map_err(|e| HHEError::Section("exit".into(), leaving, e.to_string()));
+        self.load_room(room)?;
+        exited?;
+        Ok(())
     }
 
-    fn load_room(&mut self, room: &String) {
+    /// Types out the rest of the current room's content at once, stopping early
+    /// if the content moves to another room. Waits are skipped.
+    pub fn reveal_all(&mut self) -> Result<()> {
+        let room = self.prev_room.clone();
+        loop {
+            let current = self.data.read().unwrap().current_room.clone();
+            if current != room || !self.more_to_type(&self.current_room()?) {
+                return Ok(());
+            }
+            self.tick_content()?;
+            let mut data = self.data.write().unwrap();
+            data.display.wait_ms = 0;
+            data.display.wait_key = false;
+            data.display.clear_on_continue = false;
+            data.update_more_pages();
+        }
+    }
+
+    /// Runs the first room's pre section so the story is set up, defines
+    /// `variables` over whatever it set and then enters `room`, so its pre
+    /// section sees them. Starting at the first room only enters it once.
+    pub fn start_at(&mut self, room: &str, variables: Vec<(String, Value)>) -> Result<()> {
+        let first_room = {
+            let read = self.data.read().unwrap();
+            read.project.meta.settings.first_room.clone()
+        };
+        self.enter_room(&first_room)?;
+        for (name, value) in variables {
+            self.bind(&name, value);
+        }
+        if first_room != room {
+            self.enter_room(room)?;
+        }
+        Ok(())
+    }
+
+    /// Defines `name` in the global lisp environment and counts it as a user
+    /// variable, see [`Environment::defined_symbols`].
+    pub fn bind(&mut self, name: &str, value: Value) {
+        self.context.borrow_mut().define(Symbol::from(name), value);
+        self.defined.insert(name.to_string());
+    }
+
+    /// Evaluates each `name=value` in `assignments`, with the value read as lisp.
+    pub fn eval_assignments(&mut self, assignments: &[String]) -> Result<Vec<(String, Value)>> {
+        let mut variables = vec![];
+        for assignment in assignments {
+            let (name, value) = assignment
+                .split_once('=')
+                .ok_or(HHEError::BadAssignment(assignment.clone()))?;
+            variables.push((name.trim().to_string(), self.eval_source(value)?));
+        }
+        Ok(variables)
+    }
+
+    /// Names given to `define` or `defun` in the project's rooms and libs, or in
+    /// anything else run through [`Environment::eval_source`], and names bound
+    /// with [`Environment::bind`]. Builtins and the std library are left out.
+    pub fn defined_symbols(&self) -> Vec<String> {
+        let project = self.data.read().unwrap().project.clone();
+        let mut names = self.defined.clone();
+        for room in project.rooms.values() {
+            collect_defines(&room.pre, &mut names);
+            collect_defines(&room.post, &mut names);
+            collect_content_defines(&room.content, &mut names);
+        }
+        for lib in project.libs.values() {
+            collect_defines(lib, &mut names);
+        }
+        names.into_iter().collect()
+    }
+
+    /// Current values of every name in [`Environment::defined_symbols`] that is
+    /// bound in the global lisp environment, for the inspector and saves.
+    ///
+    /// rust_lisp can't list what is bound, so this relies on the sources being
+    /// scanned for `define` and `defun` forms naming a plain symbol. A name
+    /// built at runtime, like `(eval (list 'define name 1))`, is left out.
+    pub fn user_bindings(&self) -> Vec<(String, Value)> {
+        let ctx = self.context.borrow();
+        self.defined_symbols()
+            .into_iter()
+            .filter_map(|name| ctx.get(&Symbol::from(name.as_str())).map(|v| (name, v)))
+            .collect()
+    }
+
+    fn load_room(&mut self, room: &str) -> Result<()> {
         {
             let data = Arc::clone(&self.data);
             let mut data = data.write().unwrap();
-            data.current_room = room.clone();
-            data.display.content.0 = vec![];
+            data.current_room = room.to_string();
+            data.display.clear();
             data.display.displayed_index = 0;
+            data.display.pending.clear();
+            data.display.markup_fg.clear();
+            data.display.markup_styles.clear();
+            data.display.style_stack.clear();
+            data.display.wait_ms = 0;
+            data.display.wait_key = false;
+            data.display.clear_on_continue = false;
             data.options.options = vec![];
             data.options.selected = ListState::default();
+            *data.visits.entry(room.to_string()).or_default() += 1;
+            data.update_more_pages();
         }
 
-        let room_data = self.current_room();
-        for root in parse(&room_data.pre) {
-            eval(
-                Rc::clone(&self.context),
-                &root.expect("Failed to parse lisp"),
-            )
-            .expect(&format!(
-                "Failed to evaluate lisp block in pre section of room {}",
-                room
-            ));
+        let room_data = self.current_room()?;
+        self.apply_room_meta(room, &room_data.meta);
+        self.eval_source(&room_data.pre)
+            .map_err(|e| HHEError::Section("pre".into(), room.to_string(), e.to_string()))?;
+        Ok(())
+    }
+
+    /// Applies a room's front matter, leaving out anything it doesn't set.
+    fn apply_room_meta(&mut self, room: &str, meta: &RoomMeta) {
+        let mut data = self.data.write().unwrap();
+        if let Some(title) = &meta.title {
+            data.title.content = title.clone();
+            data.title.show = true;
+        }
+        if let Some(v) = meta.title_fg {
+            data.set_color(ColorTarget::TitleFg, v);
+        }
+        if let Some(v) = meta.title_bg {
+            data.set_color(ColorTarget::TitleBg, v);
+        }
+        if let Some(v) = meta.delay {
+            data.display.delay = v;
+        }
+        if let Some(v) = meta.reveal {
+            data.display.reveal = v;
+        }
+        if let Some(v) = meta.display_fg {
+            data.set_color(ColorTarget::DisplayFg, v);
+        }
+        if let Some(v) = meta.display_bg {
+            data.set_color(ColorTarget::DisplayBg, v);
+        }
+        if let Some(v) = meta.display_ac {
+            data.set_color(ColorTarget::DisplayAc, v);
+        }
+        if let Some(v) = meta.fg {
+            data.display.current_fg = v;
+        }
+        if let Some(v) = meta.bg {
+            data.display.current_bg = v;
+        }
+        if meta.ending {
+            data.endings.insert(room.to_string());
         }
     }
 
-    fn current_room(&self) -> Room {
+    /// Parses and evaluates every expression in `source` in the global context.
+    pub fn eval_source(&mut self, source: &str) -> Result<Value> {
+        collect_defines(source, &mut self.defined);
+        let mut last = Value::NIL;
+        for root in parse(source) {
+            let root = root.map_err(|e| HHEError::Lisp(e.msg))?;
+            last = eval(Rc::clone(&self.context), &root).map_err(|e| HHEError::Lisp(e.msg))?;
+        }
+        Ok(last)
+    }
+
+    fn current_room(&self) -> Result<Room> {
         let data = Arc::clone(&self.data);
         let data = data.read().unwrap();
         let room_data = data
             .project
             .rooms
             .get(&data.current_room)
-            .expect(&format!("Couldn't find the room {:?}", data.current_room));
-        room_data.clone()
-    }
-
-    fn tick_content(&mut self) {
-        let this_room = self.current_room();
-        let data_arc = Arc::clone(&self.data);
-        let (fg, bg, bold, italic, crossed, underline, too_far) = {
-            let data = data_arc.read().unwrap();
-            let too_far = data.display.displayed_index < this_room.content.len();
-            (
-                data.display.current_fg,
-                data.display.current_bg,
-                data.display.bold,
-                data.display.italic,
-                data.display.crossed,
-                data.display.underline,
-                too_far,
-            )
-        };
+            .ok_or(HHEError::RoomNotFound(data.current_room.clone()))?;
+        Ok(room_data.clone())
+    }
+
+    /// Whether `room` still has content, or interpolated text, left to type out.
+    fn more_to_type(&self, room: &Room) -> bool {
+        let data = self.data.read().unwrap();
+        data.display.displayed_index < room.content.len() || !data.display.pending.is_empty()
+    }
 
-        if too_far {
-            let value = {
-                let data = data_arc.read().unwrap();
-                &this_room.content[data.display.displayed_index]
+    /// Types out one step of content for the reveal mode: a character, a word,
+    /// a line or everything up to the next wait.
+    fn reveal_step(&mut self) -> Result<()> {
+        let room = self.prev_room.clone();
+        loop {
+            let before = self.data.read().unwrap().display.content.0.len();
+            self.tick_content()?;
+            let done = {
+                let mut data = self.data.write().unwrap();
+                let display = &mut data.display;
+                let typed = match display.content.0.len() > before {
+                    true => display.content.0.last().map(|v| v.ch),
+                    false => None,
+                };
+                let pause = match typed {
+                    Some('.' | '!' | '?' | '\n') => 6,
+                    Some(',') => 3,
+                    _ => 0,
+                };
+                if display.reveal == RevealMode::Punctuation && pause > 0 {
+                    display.wait_ms = display.scaled_delay(self.speed) * pause;
+                }
+                if data.current_room != room || data.display.waiting() {
+                    return Ok(());
+                }
+                match data.display.reveal {
+                    RevealMode::Char | RevealMode::Punctuation => true,
+                    RevealMode::Word => typed.is_some_and(char::is_whitespace),
+                    RevealMode::Line => typed == Some('\n'),
+                    RevealMode::Instant => false,
+                }
             };
-            match value {
-                crate::project::Content::Char(c) => {
-                    let new = ContentChar {
-                        bg,
-                        fg,
-                        bold,
-                        italic,
-                        underline,
-                        crossed,
-                        ch: *c,
-                    };
-                    let mut data = data_arc.write().unwrap();
-                    data.display.content.0.push(new);
+            if done || !self.more_to_type(&self.current_room()?) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn tick_content(&mut self) -> Result<()> {
+        let this_room = self.current_room()?;
+        let item = {
+            // interpolated text and chosen branches are typed out before moving on
+            let mut data = self.data.write().unwrap();
+            match data.display.pending.pop_front() {
+                Some(item) => Some(item),
+                None if data.display.displayed_index < this_room.content.len() => {
+                    data.display.displayed_index += 1;
+                    Some(this_room.content[data.display.displayed_index - 1].clone())
                 }
-                crate::project::Content::Lisp(lisp) => {
-                    for root in parse(&lisp) {
-                        eval(
-                            Rc::clone(&self.context),
-                            &root.expect("Failed to parse lisp"),
-                        )
-                        .expect("Failed to evaluate lisp");
+                None => None,
+            }
+        };
+        let typed = match item {
+            Some(item) => self.type_content(item).map_err(|e| {
+                HHEError::Section("content".into(), self.prev_room.clone(), e.to_string()).into()
+            }),
+            None => Ok(()),
+        };
+        self.data.write().unwrap().update_more_pages();
+        typed
+    }
+
+    /// Types out one piece of content. Anything it expands to, like the text
+    /// of `` `=expr` `` or the chosen branch of an `if`, goes to the front of
+    /// `pending` and its first piece is typed straight away.
+    fn type_content(&mut self, item: crate::project::Content) -> Result<()> {
+        let expanded: Vec<crate::project::Content> = match item {
+            crate::project::Content::Char(c) => {
+                let mut data = self.data.write().unwrap();
+                let new = data.display.to_content_char(c);
+                data.display.content.0.push(new);
+                return Ok(());
+            }
+            crate::project::Content::Lisp(lisp) => {
+                self.eval_source(&lisp)?;
+                return Ok(());
+            }
+            crate::project::Content::Markup(markup) => {
+                self.data.write().unwrap().display.apply_markup(markup);
+                return Ok(());
+            }
+            crate::project::Content::PageBreak => {
+                let mut data = self.data.write().unwrap();
+                data.display.wait_key = true;
+                data.display.clear_on_continue = true;
+                return Ok(());
+            }
+            crate::project::Content::Interpolate(lisp) => {
+                let text = match self.eval_source(&lisp)? {
+                    Value::String(s) => s,
+                    v => v.to_string(),
+                };
+                text.chars().map(crate::project::Content::Char).collect()
+            }
+            crate::project::Content::Dialogue { speaker, line } => {
+                let (character, delay) = {
+                    let data = self.data.read().unwrap();
+                    let character = data
+                        .character(&speaker)
+                        .ok_or(HHEError::UnknownCharacter(speaker.clone()))?;
+                    (character, data.display.delay)
+                };
+                dialogue_content(&speaker, character, delay, line)
+            }
+            crate::project::Content::If {
+                branches,
+                otherwise,
+            } => {
+                let mut chosen = otherwise;
+                for (condition, branch) in branches {
+                    let value = self.eval_source(&condition)?;
+                    let truthy: bool = (&value).into();
+                    if truthy {
+                        chosen = branch;
+                        break;
                     }
                 }
+                chosen
             }
+        };
+
+        let first = {
+            let mut data = self.data.write().unwrap();
+            for item in expanded.into_iter().rev() {
+                data.display.pending.push_front(item);
+            }
+            data.display.pending.pop_front()
+        };
+        match first {
+            Some(item) => self.type_content(item),
+            None => Ok(()),
         }
+    }
+}
 
-        {
-            let mut data = data_arc.write().unwrap();
+/// Whether typing out `content` may reach a `` `page` `` break. An `if`
+/// counts when any branch has one, since its condition isn't known until it
+/// is typed, when only the chosen branch is left in the pending content.
+fn has_page_break<'a>(mut content: impl Iterator<Item = &'a crate::project::Content>) -> bool {
+    content.any(|item| match item {
+        crate::project::Content::PageBreak => true,
+        crate::project::Content::Dialogue { line, .. } => has_page_break(line.iter()),
+        crate::project::Content::If {
+            branches,
+            otherwise,
+        } => {
+            branches.iter().any(|(_, v)| has_page_break(v.iter()))
+                || has_page_break(otherwise.iter())
+        }
+        _ => false,
+    })
+}
+
+/// What a dialogue line is typed out as: the speaker's name in bold and
+/// their colour, then the line in their text colour and speed. `delay` is
+/// the delay to go back to afterwards.
+fn dialogue_content(
+    id: &str,
+    character: Character,
+    delay: i64,
+    line: Vec<crate::project::Content>,
+) -> Vec<crate::project::Content> {
+    use crate::project::Content;
+    let name = character.name.unwrap_or(id.to_string());
+    let name_style = Style {
+        fg: character.color,
+        bold: Some(true),
+        ..Default::default()
+    };
+    let text_style = Style {
+        fg: character.text_color,
+        ..Default::default()
+    };
+
+    let mut content = vec![Content::Markup(Markup::PushStyle(name_style))];
+    content.extend(format!("{}:", name).chars().map(Content::Char));
+    content.push(Content::Markup(Markup::PopStyle));
+    content.push(Content::Char(' '));
+    content.push(Content::Markup(Markup::PushStyle(text_style)));
+    if let Some(v) = character.delay {
+        content.push(Content::Markup(Markup::Delay(v)));
+    }
+    content.extend(line);
+    content.push(Content::Markup(Markup::PopStyle));
+    if character.delay.is_some() {
+        content.push(Content::Markup(Markup::Delay(delay)));
+    }
+    content
+}
 
-            if data.display.displayed_index < this_room.content.len() {
-                data.display.displayed_index += 1;
+fn collect_content_defines(content: &[crate::project::Content], names: &mut BTreeSet<String>) {
+    for item in content {
+        match item {
+            crate::project::Content::Char(_)
+            | crate::project::Content::Markup(_)
+            | crate::project::Content::PageBreak => {}
+            crate::project::Content::Lisp(lisp) | crate::project::Content::Interpolate(lisp) => {
+                collect_defines(lisp, names)
+            }
+            crate::project::Content::Dialogue { line, .. } => collect_content_defines(line, names),
+            crate::project::Content::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, branch) in branches {
+                    collect_defines(condition, names);
+                    collect_content_defines(branch, names);
+                }
+                collect_content_defines(otherwise, names);
             }
         }
     }
 }
 
+fn collect_defines(source: &str, names: &mut BTreeSet<String>) {
+    fn walk(value: &Value, names: &mut BTreeSet<String>) {
+        if let Value::List(list) = value {
+            let items: Vec<Value> = list.into_iter().collect();
+            if let [Value::Symbol(keyword), Value::Symbol(name), ..] = items.as_slice() {
+                if keyword.0 == "define" || keyword.0 == "defun" {
+                    names.insert(name.0.clone());
+                }
+            }
+            for item in &items {
+                walk(item, names);
+            }
+        }
+    }
+    for root in parse(source).flatten() {
+        walk(&root, names);
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct EnvData {
     pub title: TitleData,
@@ -172,6 +629,168 @@ pub struct EnvData {
     pub project: Project,
     pub debug: Vec<String>,
     pub quit: bool,
+    /// How many times each room has been entered
+    pub visits: HashMap<String, usize>,
+    /// Rooms marked `ending = true` that have been reached
+    pub endings: BTreeSet<String>,
+    /// Styles named with `style/define`, looked up before `[styles]`
+    pub styles: BTreeMap<String, Style>,
+    /// Characters from `character/define`, looked up before `[characters]`
+    pub characters: BTreeMap<String, Character>,
+    /// Colours changing over time, from `display/bg/tween` and friends
+    pub tweens: Vec<Tween>,
+    /// Colours fading back from `display/flash`, drawn over any tween
+    pub flashes: Vec<Flash>,
+}
+
+/// Pane colours that can be tweened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorTarget {
+    DisplayFg,
+    DisplayBg,
+    DisplayAc,
+    TitleFg,
+    TitleBg,
+}
+
+/// A colour going from `from` to `to` over `duration` milliseconds.
+#[derive(Clone, Debug)]
+pub struct Tween {
+    pub target: ColorTarget,
+    pub from: Color,
+    pub to: Color,
+    pub elapsed: i64,
+    pub duration: i64,
+}
+
+/// A colour fading from `color` back to `under` over `duration` milliseconds.
+/// A tween of the same colour moves `under` instead of what is shown.
+#[derive(Clone, Debug)]
+pub struct Flash {
+    pub target: ColorTarget,
+    pub color: Color,
+    pub under: Color,
+    pub elapsed: i64,
+    pub duration: i64,
+}
+
+impl EnvData {
+    fn color_mut(&mut self, target: ColorTarget) -> &mut Color {
+        match target {
+            ColorTarget::DisplayFg => &mut self.display.display_fg,
+            ColorTarget::DisplayBg => &mut self.display.display_bg,
+            ColorTarget::DisplayAc => &mut self.display.display_ac,
+            ColorTarget::TitleFg => &mut self.title.fg,
+            ColorTarget::TitleBg => &mut self.title.bg,
+        }
+    }
+
+    /// The pane colour without any flash over it.
+    fn base_mut(&mut self, target: ColorTarget) -> &mut Color {
+        match self.flashes.iter().position(|v| v.target == target) {
+            Some(i) => &mut self.flashes[i].under,
+            None => self.color_mut(target),
+        }
+    }
+
+    /// Sets a pane colour straight away, stopping any tween or flash of it.
+    pub fn set_color(&mut self, target: ColorTarget, color: Color) {
+        self.tweens.retain(|v| v.target != target);
+        self.flashes.retain(|v| v.target != target);
+        *self.color_mut(target) = color;
+    }
+
+    /// Moves a pane colour from what it is now to `to` over `ms` milliseconds.
+    pub fn tween_color(&mut self, target: ColorTarget, to: Color, ms: i64) {
+        let from = *self.base_mut(target);
+        self.tweens.retain(|v| v.target != target);
+        if ms <= 0 {
+            *self.base_mut(target) = to;
+            return;
+        }
+        self.tweens.push(Tween {
+            target,
+            from,
+            to,
+            elapsed: 0,
+            duration: ms,
+        });
+    }
+
+    /// Sets a pane colour to `color`, then fades it back over `ms`
+    /// milliseconds. A tween in progress carries on underneath, so the flash
+    /// fades into wherever the tween has got to.
+    pub fn flash_color(&mut self, target: ColorTarget, color: Color, ms: i64) {
+        if ms <= 0 {
+            return;
+        }
+        let under = *self.base_mut(target);
+        self.flashes.retain(|v| v.target != target);
+        self.flashes.push(Flash {
+            target,
+            color,
+            under,
+            elapsed: 0,
+            duration: ms,
+        });
+        *self.color_mut(target) = color;
+    }
+
+    fn step_tweens(&mut self, dt: i64) {
+        let mut tweens = std::mem::take(&mut self.tweens);
+        for tween in &mut tweens {
+            tween.elapsed += dt;
+            let t = (tween.elapsed as f64 / tween.duration as f64).min(1.0);
+            *self.base_mut(tween.target) = tween.from.lerp(tween.to, t);
+        }
+        tweens.retain(|v| v.elapsed < v.duration);
+        self.tweens = tweens;
+
+        let mut flashes = std::mem::take(&mut self.flashes);
+        for flash in &mut flashes {
+            flash.elapsed += dt;
+            let t = (flash.elapsed as f64 / flash.duration as f64).min(1.0);
+            *self.color_mut(flash.target) = flash.color.lerp(flash.under, t);
+        }
+        flashes.retain(|v| v.elapsed < v.duration);
+        self.flashes = flashes;
+    }
+
+    /// The style called `name`, from `style/define` or the story's `[styles]`.
+    pub fn named_style(&self, name: &str) -> Option<Style> {
+        self.styles
+            .get(name)
+            .or(self.project.meta.styles.get(name))
+            .copied()
+    }
+
+    /// Whether a `` `page` `` break is still ahead in the current room, or
+    /// waiting for the player. The room's options are hidden until the last page.
+    pub fn more_pages(&self) -> bool {
+        self.display.more_pages
+    }
+
+    /// Works out [`EnvData::more_pages`] again, after content was typed or cleared.
+    fn update_more_pages(&mut self) {
+        let rest = self
+            .project
+            .rooms
+            .get(&self.current_room)
+            .and_then(|room| room.content.get(self.display.displayed_index..))
+            .unwrap_or_default();
+        self.display.more_pages = self.display.clear_on_continue
+            || has_page_break(self.display.pending.iter())
+            || has_page_break(rest.iter());
+    }
+
+    /// The character with id `id`, from `character/define` or the story's
+    /// `[characters]`.
+    pub fn character(&self, id: &str) -> Option<Character> {
+        self.characters
+            .get(id)
+            .or(self.project.meta.characters.get(id))
+            .cloned()
+    }
 }
 
 #[derive(Clone, Default, Debug)]
@@ -204,9 +823,146 @@ pub struct DisplayData {
     pub crossed: bool,
     pub underline: bool,
     pub scroll: i32,
+    /// Content still to be typed out before the room's next item, from
+    /// `` `=expr` `` text or the chosen branch of an `if`
+    pub pending: VecDeque<crate::project::Content>,
+    /// How many times the content has been cleared, so frontends can tell a
+    /// clear apart from content that was replaced by as much new text
+    pub clears: usize,
+    /// Colours to go back to when a `[color=...]` in the content closes
+    pub markup_fg: Vec<Color>,
+    /// Styles to go back to when a dialogue line ends
+    pub markup_styles: Vec<Style>,
+    /// Styles saved by `style/push`, emptied when a room is entered
+    pub style_stack: Vec<Style>,
+    /// Milliseconds left in a `(wait ms)` before typing carries on
+    pub wait_ms: i64,
+    /// Set by `(wait-key)` until the player presses a continue key
+    pub wait_key: bool,
+    /// Set at a `` `page` `` break, so continuing starts a new page
+    pub clear_on_continue: bool,
+    /// See [`EnvData::more_pages`]
+    pub more_pages: bool,
+    /// How much is typed out each `delay`
+    pub reveal: RevealMode,
+    /// Animation for newly typed characters
+    pub effect: Effect,
+    /// Milliseconds of play so far, sped up by the player's speed. Effects
+    /// are drawn at this time, see [`Content::to_animated_line`]
+    pub clock: u64,
+}
+
+/// How content is typed out, set with `delay/mode` or `reveal` in front matter.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RevealMode {
+    /// A character at a time
+    #[default]
+    Char,
+    /// A character at a time, pausing after `.`, `!`, `?`, `,` and line breaks
+    Punctuation,
+    /// A word and the space after it at a time
+    Word,
+    /// A line at a time
+    Line,
+    /// Everything at once, up to the next wait
+    Instant,
+}
+
+impl RevealMode {
+    pub const NAMES: &'static [&'static str] = &["char", "punctuation", "word", "line", "instant"];
+
+    pub fn from_name(name: &str) -> Option<RevealMode> {
+        Some(match name {
+            "char" => RevealMode::Char,
+            "punctuation" => RevealMode::Punctuation,
+            "word" => RevealMode::Word,
+            "line" => RevealMode::Line,
+            "instant" => RevealMode::Instant,
+            _ => return None,
+        })
+    }
 }
 
 impl DisplayData {
+    /// Empties the content, counting the clear in `clears`.
+    pub fn clear(&mut self) {
+        self.content.0.clear();
+        self.clears += 1;
+    }
+
+    /// `delay` sped up by the player's `speed`, at least a millisecond.
+    pub fn scaled_delay(&self, speed: f64) -> i64 {
+        ((self.delay as f64 / speed).round() as i64).max(1)
+    }
+
+    /// Whether typing is paused by `wait` or `wait-key`.
+    pub fn waiting(&self) -> bool {
+        self.wait_ms > 0 || self.wait_key
+    }
+
+    /// The current content style, with every field set.
+    pub fn style(&self) -> Style {
+        Style {
+            fg: Some(self.current_fg),
+            bg: Some(self.current_bg),
+            bold: Some(self.bold),
+            italic: Some(self.italic),
+            crossed: Some(self.crossed),
+            underline: Some(self.underline),
+        }
+    }
+
+    /// Like [`DisplayData::to_content_char`], with `style` on top of the
+    /// current style.
+    pub fn to_styled_char(&self, ch: char, style: &Style) -> ContentChar {
+        let mut new = self.to_content_char(ch);
+        new.fg = style.fg.unwrap_or(new.fg);
+        new.bg = style.bg.unwrap_or(new.bg);
+        new.bold = style.bold.unwrap_or(new.bold);
+        new.italic = style.italic.unwrap_or(new.italic);
+        new.crossed = style.crossed.unwrap_or(new.crossed);
+        new.underline = style.underline.unwrap_or(new.underline);
+        new
+    }
+
+    pub fn apply_style(&mut self, style: &Style) {
+        self.current_fg = style.fg.unwrap_or(self.current_fg);
+        self.current_bg = style.bg.unwrap_or(self.current_bg);
+        self.bold = style.bold.unwrap_or(self.bold);
+        self.italic = style.italic.unwrap_or(self.italic);
+        self.crossed = style.crossed.unwrap_or(self.crossed);
+        self.underline = style.underline.unwrap_or(self.underline);
+    }
+
+    pub fn apply_markup(&mut self, markup: Markup) {
+        match markup {
+            Markup::Bold(v) => self.bold = v,
+            Markup::Italic(v) => self.italic = v,
+            Markup::Crossed(v) => self.crossed = v,
+            Markup::Underline(v) => self.underline = v,
+            Markup::PushFg(color) => {
+                self.markup_fg.push(self.current_fg);
+                self.current_fg = color;
+            }
+            Markup::PopFg => {
+                if let Some(color) = self.markup_fg.pop() {
+                    self.current_fg = color;
+                }
+            }
+            Markup::PushStyle(style) => {
+                self.markup_styles.push(self.style());
+                self.apply_style(&style);
+            }
+            Markup::PopStyle => {
+                if let Some(style) = self.markup_styles.pop() {
+                    self.apply_style(&style);
+                }
+            }
+            Markup::Delay(delay) => self.delay = delay,
+        }
+    }
+
     pub fn to_content_char(&self, ch: char) -> ContentChar {
         ContentChar {
             bg: self.current_bg,
@@ -215,6 +971,8 @@ impl DisplayData {
             italic: self.italic,
             crossed: self.crossed,
             underline: self.underline,
+            effect: self.effect,
+            typed_at: self.clock,
             ch,
         }
     }
@@ -229,6 +987,49 @@ pub struct OptionData {
 pub struct OptionDataSingle {
     pub name: Content,
     pub action: Value,
+    /// Human readable form of `action`, for developer tools
+    pub description: String,
+}
+
+/// Animations drawn over content, set with `effect`.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Effect {
+    #[default]
+    None,
+    /// Jumps a column left and right
+    Shake,
+    /// A band of brightness moving along the text
+    Wave,
+    /// Cycles through the colours of the rainbow
+    Rainbow,
+    /// Shows and hides
+    Blink,
+    /// Fades in from the background colour once typed
+    FadeIn,
+}
+
+impl Effect {
+    pub const NAMES: &'static [&'static str] =
+        &["none", "shake", "wave", "rainbow", "blink", "fade-in"];
+
+    pub fn from_name(name: &str) -> Option<Effect> {
+        Some(match name {
+            "none" => Effect::None,
+            "shake" => Effect::Shake,
+            "wave" => Effect::Wave,
+            "rainbow" => Effect::Rainbow,
+            "blink" => Effect::Blink,
+            "fade-in" => Effect::FadeIn,
+            _ => return None,
+        })
+    }
+}
+
+/// Cheap repeatable noise, so shaking text stays put within a frame.
+fn noise(a: u64, b: u64) -> u64 {
+    let x = a.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ b.wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    x ^ (x >> 29)
 }
 
 #[derive(Clone, Debug)]
@@ -240,6 +1041,9 @@ pub struct ContentChar {
     pub italic: bool,
     pub underline: bool,
     pub crossed: bool,
+    pub effect: Effect,
+    /// When the character was typed, on the [`DisplayData::clock`]
+    pub typed_at: u64,
 }
 impl Default for ContentChar {
     fn default() -> Self {
@@ -251,6 +1055,48 @@ impl Default for ContentChar {
             crossed: false,
             italic: false,
             underline: false,
+            effect: Effect::None,
+            typed_at: 0,
+        }
+    }
+}
+
+impl ContentChar {
+    fn styled(&self, ch: char, fg: Color) -> Span<'static> {
+        let mut value = Span::from(ch.to_string())
+            .fg(fg.to_ratatui_color())
+            .bg(self.bg.to_ratatui_color());
+        if self.bold {
+            value = value.bold()
+        };
+        if self.italic {
+            value = value.italic()
+        };
+        if self.crossed {
+            value = value.crossed_out()
+        };
+        if self.underline {
+            value = value.underlined()
+        };
+        value
+    }
+
+    /// The character as drawn at `now`, `index` characters into its line.
+    fn animated(&self, index: usize, now: u64) -> Span<'static> {
+        let index = index as f64;
+        match self.effect {
+            Effect::None | Effect::Shake => self.styled(self.ch, self.fg),
+            Effect::Wave => {
+                let phase = (index * 0.6 - now as f64 / 150.0).sin();
+                self.styled(self.ch, self.bg.lerp(self.fg, 0.6 + 0.4 * phase))
+            }
+            Effect::Rainbow => self.styled(self.ch, Color::hue(index * 25.0 + now as f64 / 8.0)),
+            Effect::Blink if !(now / 400).is_multiple_of(2) => self.styled(' ', self.fg),
+            Effect::Blink => self.styled(self.ch, self.fg),
+            Effect::FadeIn => {
+                let t = (now.saturating_sub(self.typed_at) as f64 / 800.0).clamp(0.0, 1.0);
+                self.styled(self.ch, self.bg.lerp(self.fg, t))
+            }
         }
     }
 }
@@ -258,27 +1104,12 @@ impl Default for ContentChar {
 #[derive(Clone, Default, Debug)]
 pub struct Content(pub Vec<ContentChar>);
 impl Content {
-    pub fn to_spans(&self) -> Vec<Vec<Span>> {
+    pub fn to_spans(&self) -> Vec<Vec<Span<'_>>> {
         let mut lines = vec![];
         let mut cur = vec![];
         for ch in &self.0 {
             if ch.ch != '\n' {
-                let mut value = Span::from(ch.ch.to_string())
-                    .fg(ch.fg.to_ratatui_color())
-                    .bg(ch.bg.to_ratatui_color());
-                if ch.bold {
-                    value = value.bold()
-                };
-                if ch.italic {
-                    value = value.italic()
-                };
-                if ch.crossed {
-                    value = value.crossed_out()
-                };
-                if ch.underline {
-                    value = value.underlined()
-                };
-                cur.push(value);
+                cur.push(ch.styled(ch.ch, ch.fg));
             } else {
                 lines.push(cur);
                 cur = vec![];
@@ -287,12 +1118,44 @@ impl Content {
         if !cur.is_empty() {
             lines.push(cur);
         }
-        return lines;
+        lines
     }
-    pub fn to_line(&self) -> Vec<Line> {
-        self.to_spans().into_iter().map(|v| Line::from(v)).collect()
+    pub fn to_line(&self) -> Vec<Line<'_>> {
+        self.to_spans().into_iter().map(Line::from).collect()
     }
-    pub fn to_text(&self) -> Text {
+    /// Like [`Content::to_line`], with effects drawn as they look at `now`
+    /// on the [`DisplayData::clock`].
+    pub fn to_animated_line(&self, now: u64) -> Vec<Line<'static>> {
+        let mut lines: Vec<&[ContentChar]> = self.0.split(|v| v.ch == '\n').collect();
+        if lines.last().is_some_and(|v| v.is_empty()) {
+            lines.pop();
+        }
+        lines
+            .into_iter()
+            .map(|line| {
+                let mut spans = vec![];
+                let mut jumped = false;
+                for (i, ch) in line.iter().enumerate() {
+                    // a shaking run is padded by a space on one side, which
+                    // side changing every few frames, so the line keeps its length
+                    let shaking = ch.effect == Effect::Shake;
+                    if shaking && (i == 0 || line[i - 1].effect != Effect::Shake) {
+                        jumped = noise(i as u64, now / 70).is_multiple_of(2);
+                        if jumped {
+                            spans.push(Span::raw(" "));
+                        }
+                    }
+                    spans.push(ch.animated(i, now));
+                    let run_ends = line.get(i + 1).is_none_or(|v| v.effect != Effect::Shake);
+                    if shaking && run_ends && !jumped {
+                        spans.push(Span::raw(" "));
+                    }
+                }
+                Line::from(spans)
+            })
+            .collect()
+    }
+    pub fn to_text(&self) -> Text<'_> {
         Text::from(self.to_line())
     }
     pub fn to_raw(&self) -> String {
@@ -315,7 +1178,15 @@ macro_rules! insert_func {
     }};
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Environment {
+    // `EnvData` holds lisp values, so it never leaves the thread it was made on
+    #[allow(clippy::arc_with_non_send_sync)]
     pub fn new() -> Environment {
         Environment {
             context: Rc::new(RefCell::new(default_env())),
@@ -325,6 +1196,8 @@ impl Environment {
             prev_time: chrono::offset::Utc::now().timestamp_millis(),
             tick_passed: 0,
             prev_room: "".to_string(),
+            defined: BTreeSet::new(),
+            speed: 1.0,
         }
     }
 
@@ -383,6 +1256,8 @@ impl Environment {
             insert_func!(self, "title/name/set", set_name);
             insert_func!(self, "title/fg/set", set_fg);
             insert_func!(self, "title/bg/set", set_bg);
+            insert_func!(self, "title/fg/tween", tween_fg);
+            insert_func!(self, "title/bg/tween", tween_bg);
             insert_func!(self, "title/bold", bold);
             insert_func!(self, "title/italic", italic);
             insert_func!(self, "title/crossed", crossed);
@@ -398,6 +1273,9 @@ impl Environment {
         {
             use lisp::content::*;
             insert_func!(self, "delay/set", set_delay);
+            insert_func!(self, "delay/mode", set_delay_mode);
+            insert_func!(self, "wait", wait);
+            insert_func!(self, "wait-key", wait_key);
             insert_func!(self, "fg/set", set_content_fg);
             insert_func!(self, "bg/set", set_content_bg);
             insert_func!(self, "bold", bold);
@@ -405,6 +1283,7 @@ impl Environment {
             insert_func!(self, "crossed", crossed);
             insert_func!(self, "underline", underline);
             insert_func!(self, "reset", reset);
+            insert_func!(self, "effect", set_effect);
 
             insert_func!(self, "display/fg/set", set_fg);
             insert_func!(self, "display/bg/set", set_bg);
@@ -412,6 +1291,10 @@ impl Environment {
             insert_func!(self, "display/fg/get", get_fg);
             insert_func!(self, "display/bg/get", get_bg);
             insert_func!(self, "display/ac/get", get_ac);
+            insert_func!(self, "display/fg/tween", tween_fg);
+            insert_func!(self, "display/bg/tween", tween_bg);
+            insert_func!(self, "display/ac/tween", tween_ac);
+            insert_func!(self, "display/flash", flash);
 
             insert_func!(self, "content/clear", content_clear);
             insert_func!(self, "content/get-raw", content_get_raw);
@@ -422,6 +1305,29 @@ impl Environment {
             insert_func!(self, "content/scroll/set", content_scroll_set);
             insert_func!(self, "content/scroll/get", content_scroll_get);
         }
+        {
+            use lisp::style::*;
+            insert_func!(self, "style/new", style_new);
+            insert_func!(self, "style/get", style_get);
+            insert_func!(self, "style/apply", style_apply);
+            insert_func!(self, "style", style_apply);
+            insert_func!(self, "style/define", style_define);
+            insert_func!(self, "style/push", style_push);
+            insert_func!(self, "style/pop", style_pop);
+        }
+        {
+            use lisp::character::*;
+            insert_func!(self, "character/define", character_define);
+        }
+        for key in lisp::style::STYLE_KEYS
+            .iter()
+            .chain(lisp::character::CHARACTER_KEYS)
+        {
+            let keyword = Symbol::from(format!(":{}", key).as_str());
+            self.context
+                .borrow_mut()
+                .define(keyword.clone(), Value::Symbol(keyword));
+        }
         {
             use lisp::option::*;
             insert_func!(self, "option/goto", option_goto);
@@ -434,30 +1340,15 @@ impl Environment {
             insert_func!(self, "debug", debug);
             insert_func!(self, "exit", exit);
             insert_func!(self, "to-string", to_string);
-            // insert_func!(self, "import", import);
-
-            self.context.borrow_mut().define(
-                Symbol::from("import"),
-                Value::NativeFunc(|env, args| {
-                    let module: &Symbol = require_typed_arg("import", &args, 0)?;
-                    let source = match module.0.as_str() {
-                        "escape" => include_str!("./libs/escape.hh3"),
-                        "math" => include_str!("./libs/math.hh3"),
-                        "quick" => include_str!("./libs/quick.hh3"),
-                        "std" => include_str!("./libs/std.hh3"),
-                        _ => "",
-                    };
-
-                    let parsed: Result<Vec<Value>, _> = parse(source).collect();
-                    let parsed = parsed.unwrap();
-
-                    for root in parsed {
-                        eval(env.clone(), &root).expect("Failed to evaluate module");
-                    }
-
-                    Ok(Value::NIL)
-                }),
+            // a closure can't be called again while it runs, which importing
+            // a module that imports another module would do
+            let mut ctx = self.context.borrow_mut();
+            ctx.define(
+                Symbol::from(CONTAINER_SYMBOL),
+                Value::Foreign(Rc::new(Arc::clone(&self.data))),
             );
+            ctx.define(Symbol::from("import"), Value::NativeFunc(import));
+            drop(ctx);
 
             insert_func!(self, "intrinsic", intrinsic);
 
@@ -484,3 +1375,255 @@ impl Environment {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(text: &str) -> Color {
+        Color::parse(text).unwrap()
+    }
+
+    #[test]
+    fn user_bindings_come_from_define_forms() {
+        let mut environment = Environment::new().register_all();
+        {
+            let mut data = environment.data.write().unwrap();
+            let room = "--- pre\n(define in-room 1)\n--- content\n`(define in-content 2)`\n";
+            let room = crate::parser::parse_room_source("index", room, false).unwrap();
+            data.project.rooms.insert("index".to_string(), room);
+        }
+        environment.enter_room("index").unwrap();
+        environment.eval_source("(define typed 3)").unwrap();
+        environment
+            .eval_source("(eval (list 'define 'built 4))")
+            .unwrap();
+
+        let names: Vec<String> = environment
+            .user_bindings()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        // in-content isn't typed out yet, so it's found but not bound
+        assert_eq!(names, ["in-room", "typed"]);
+        assert_eq!(environment.eval_source("built").unwrap().to_string(), "4");
+    }
+
+    #[test]
+    fn start_at_binds_variables_before_entering_the_room() {
+        let start = |room: &str, variables: &[&str]| {
+            let mut environment = Environment::new().register_all();
+            {
+                let mut data = environment.data.write().unwrap();
+                data.project.meta.settings.first_room = "index".to_string();
+                for (id, source) in [
+                    ("index", "--- pre\n(define gold 0)\n"),
+                    ("shop", "--- pre\n(define seen gold)\n"),
+                ] {
+                    let room = crate::parser::parse_room_source(id, source, false).unwrap();
+                    data.project.rooms.insert(id.to_string(), room);
+                }
+            }
+            let assignments: Vec<String> = variables.iter().map(|v| v.to_string()).collect();
+            let variables = environment.eval_assignments(&assignments).unwrap();
+            environment.start_at(room, variables).unwrap();
+            environment
+        };
+
+        let mut environment = start("shop", &["gold=5", "extra=1"]);
+        assert_eq!(environment.eval_source("seen").unwrap().to_string(), "5");
+        let names: Vec<String> = environment
+            .user_bindings()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        assert!(names.contains(&"extra".to_string()));
+
+        // the first room's own setup doesn't undo them
+        let mut environment = start("index", &["gold=5"]);
+        assert_eq!(environment.eval_source("gold").unwrap().to_string(), "5");
+    }
+
+    #[test]
+    fn dialogue_styles_are_kept_apart_from_style_push() {
+        let mut environment = Environment::new().register_all();
+        environment
+            .eval_source("(character/define 'g :text_color (color 'red))")
+            .unwrap();
+        let red = environment.data.read().unwrap().characters["g"].text_color;
+        assert_eq!(red, Some(color("red")));
+
+        let apply = |environment: &Environment, markup: Markup| {
+            let mut data = environment.data.write().unwrap();
+            data.display.apply_markup(markup)
+        };
+        let text_style = Style {
+            fg: red,
+            ..Default::default()
+        };
+        apply(&environment, Markup::PushStyle(text_style));
+        // a style/push in the line that's never popped
+        environment
+            .eval_source("(style/push (style/new :bold true))")
+            .unwrap();
+        apply(&environment, Markup::PopStyle);
+        let data = environment.data.read().unwrap();
+        assert_eq!(data.display.current_fg, Color::default());
+        assert!(!data.display.bold);
+        assert_eq!(data.display.style_stack.len(), 1);
+    }
+
+    #[test]
+    fn more_pages_follows_the_branch_that_is_taken() {
+        let mut environment = Environment::new().register_all();
+        let rooms = [
+            ("skipped", "`if false`\none`page`\n`end`two"),
+            ("taken", "`if false`a`else`b`page``end`c"),
+            ("paged", "one`page`two"),
+        ];
+        {
+            let mut data = environment.data.write().unwrap();
+            data.display.delay = 1;
+            for (id, content) in rooms {
+                let source = format!("--- content\n{}\n", content);
+                let room = crate::parser::parse_room_source(id, &source, false).unwrap();
+                data.project.rooms.insert(id.to_string(), room);
+            }
+        }
+        let more_pages = |environment: &Environment| environment.data.read().unwrap().more_pages();
+        // a second at a millisecond a character types out any of these rooms
+        let type_out = |environment: &mut Environment| environment.step(1000).unwrap();
+
+        // the page break might be ahead until the `if` is typed
+        environment.enter_room("skipped").unwrap();
+        assert!(more_pages(&environment));
+        type_out(&mut environment);
+        assert!(!more_pages(&environment));
+        assert!(!environment.data.read().unwrap().display.wait_key);
+
+        environment.enter_room("taken").unwrap();
+        assert!(more_pages(&environment));
+        type_out(&mut environment);
+        assert!(environment.data.read().unwrap().display.wait_key);
+        assert!(more_pages(&environment));
+
+        environment.enter_room("paged").unwrap();
+        assert!(more_pages(&environment));
+        type_out(&mut environment);
+        assert!(environment.data.read().unwrap().display.wait_key);
+        assert!(more_pages(&environment));
+        environment.continue_reading();
+        assert!(!more_pages(&environment));
+        type_out(&mut environment);
+        let data = environment.data.read().unwrap();
+        assert_eq!(data.display.content.to_raw(), "two");
+        assert!(!data.more_pages());
+    }
+
+    fn content(text: &str, effect: Effect) -> Content {
+        Content(
+            text.chars()
+                .map(|ch| ContentChar {
+                    ch,
+                    fg: color("#ffffff"),
+                    effect,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn animated_lines_split_like_lines() {
+        for text in ["", "\n", "ab", "ab\n", "ab\n\ncd", "ab\n\ncd\n\n"] {
+            let plain = content(text, Effect::None);
+            assert_eq!(plain.to_animated_line(1234), plain.to_line(), "{:?}", text);
+            let shaking = content(text, Effect::Shake);
+            let widths = |lines: Vec<Line>| lines.iter().map(|v| v.width()).collect::<Vec<_>>();
+            let padded = widths(plain.to_line())
+                .into_iter()
+                .map(|v| if v > 0 { v + 1 } else { 0 })
+                .collect::<Vec<_>>();
+            assert_eq!(widths(shaking.to_animated_line(1234)), padded, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn fade_in_follows_the_environment_clock() {
+        let mut environment = Environment::new();
+        environment.speed = 2.0;
+        environment.step(100).unwrap();
+        let mut data = environment.data.write().unwrap();
+        assert_eq!(data.display.clock, 200);
+        data.display.current_fg = color("#ffffff");
+        data.display.effect = Effect::FadeIn;
+        let ch = data.display.to_content_char('a');
+        assert_eq!(ch.typed_at, 200);
+        drop(data);
+
+        let fg = |environment: &Environment| {
+            let data = environment.data.read().unwrap();
+            let line = Content(vec![ch.clone()]).to_animated_line(data.display.clock);
+            line[0].spans[0].style.fg
+        };
+        assert_eq!(fg(&environment), Some(color("#000000").to_ratatui_color()));
+        // 400ms at double speed is the whole 800ms fade
+        environment.step(400).unwrap();
+        assert_eq!(fg(&environment), Some(color("#ffffff").to_ratatui_color()));
+    }
+
+    #[test]
+    fn tweens_finish_on_their_end_colour() {
+        let mut data = EnvData::default();
+        data.set_color(ColorTarget::DisplayBg, color("#000000"));
+        data.tween_color(ColorTarget::DisplayBg, color("#ffffff"), 100);
+        data.step_tweens(50);
+        assert_eq!(data.display.display_bg, color("#808080"));
+        data.step_tweens(60);
+        assert_eq!(data.display.display_bg, color("#ffffff"));
+        assert!(data.tweens.is_empty());
+    }
+
+    #[test]
+    fn instant_tweens_and_flashes() {
+        let mut data = EnvData::default();
+        data.set_color(ColorTarget::DisplayBg, color("#000000"));
+        data.tween_color(ColorTarget::DisplayBg, color("#ffffff"), 0);
+        assert_eq!(data.display.display_bg, color("#ffffff"));
+        data.flash_color(ColorTarget::DisplayBg, color("#ff0000"), -5);
+        assert_eq!(data.display.display_bg, color("#ffffff"));
+        assert!(data.tweens.is_empty() && data.flashes.is_empty());
+    }
+
+    #[test]
+    fn flashes_fade_into_a_running_tween() {
+        let mut data = EnvData::default();
+        data.set_color(ColorTarget::DisplayBg, color("#000000"));
+        data.tween_color(ColorTarget::DisplayBg, color("#ffffff"), 200);
+        data.step_tweens(50);
+        data.flash_color(ColorTarget::DisplayBg, color("#ff0000"), 100);
+        assert_eq!(data.display.display_bg, color("#ff0000"));
+
+        // the tween keeps going underneath, at 100/200 when the flash is half done
+        data.step_tweens(50);
+        assert_eq!(
+            data.display.display_bg,
+            color("#ff0000").lerp(color("#808080"), 0.5)
+        );
+        data.step_tweens(50);
+        assert_eq!(data.display.display_bg, color("#bfbfbf"));
+        assert!(data.flashes.is_empty());
+        data.step_tweens(50);
+        assert_eq!(data.display.display_bg, color("#ffffff"));
+        assert!(data.tweens.is_empty());
+    }
+
+    #[test]
+    fn setting_a_colour_stops_its_flash() {
+        let mut data = EnvData::default();
+        data.flash_color(ColorTarget::DisplayBg, color("#ff0000"), 100);
+        data.set_color(ColorTarget::DisplayBg, color("#0000ff"));
+        data.step_tweens(50);
+        assert_eq!(data.display.display_bg, color("#0000ff"));
+    }
+}
@@ -3,5 +3,31 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum HHEError {
     #[error("Couldn't find folder with path `{0}`")]
-    ProjectFolderDoesntExist(String, std::io::Error)
-}
\ No newline at end of file
+    ProjectFolderDoesntExist(String, std::io::Error),
+    #[error("No option at index {0}")]
+    OptionOutOfRange(usize),
+    #[error("Lisp error: {0}")]
+    Lisp(String),
+    #[error("Couldn't find the room `{0}`")]
+    RoomNotFound(String),
+    #[error("In {0} section of room `{1}`: {2}")]
+    Section(String, String, String),
+    #[error("Expected `name=value`, found `{0}`")]
+    BadAssignment(String),
+    #[error("This story needs hhe3 {0} or newer, but this is {1}")]
+    EngineTooOld(String, String),
+    #[error("This story needs features this version of hhe3 doesn't have: {0}")]
+    MissingFeatures(String),
+    #[error("Expected a version like `1.2.0`, found `{0}`")]
+    BadVersion(String),
+    #[error("{0}did you mean `{1}`?")]
+    UnknownField(String, String),
+    #[error("Two rooms have the id `{0}`: {1} and {2}")]
+    DuplicateRoom(String, String, String),
+    #[error("{0}:{1}: {2}")]
+    RoomSyntax(String, usize, String),
+    #[error("Couldn't read the config at `{0}`: {1}")]
+    BadConfig(String, String),
+    #[error("No character called `{0}`, add them to [characters] or use character/define")]
+    UnknownCharacter(String),
+}
@@ -0,0 +1,129 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    layout::Rect,
+    style::Stylize,
+    symbols::border,
+    text::Line,
+    widgets::{Block, Clear, Paragraph, Wrap},
+    Frame,
+};
+use rust_lisp::model::Value;
+
+use crate::{environment::Environment, lisp::color::Color};
+
+/// Developer panel listing the story's variables and the engine's state,
+/// redrawn every frame so it follows the story as it runs.
+#[derive(Debug, Default)]
+pub struct Inspector {
+    pub open: bool,
+    pub scroll: u16,
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Foreign(r) if r.is::<Color>() => format!("{:?}", r.downcast_ref::<Color>().unwrap()),
+        _ => value.to_string(),
+    }
+}
+
+fn heading(text: &str) -> Line<'static> {
+    Line::from(text.to_string()).bold().underlined()
+}
+
+impl Inspector {
+    /// Handles `ev` if it belongs to the inspector, returning whether it was used.
+    pub fn input(&mut self, ev: &Event) -> bool {
+        let Event::Key(KeyEvent {
+            code,
+            kind: KeyEventKind::Press,
+            ..
+        }) = ev
+        else {
+            return false;
+        };
+        match code {
+            KeyCode::F(2) => self.open = !self.open,
+            KeyCode::PageUp if self.open => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageDown if self.open => self.scroll += 1,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn lines(environment: &Environment) -> Vec<Line<'static>> {
+        let mut lines = vec![];
+
+        lines.push(heading("Variables"));
+        for (name, value) in environment.user_bindings() {
+            lines.push(Line::from(format!("{} = {}", name, describe(&value))));
+        }
+
+        let data = environment.data.read().unwrap();
+        lines.push(Line::default());
+        lines.push(heading("Room"));
+        lines.push(Line::from(format!(
+            "{} ({}/{} shown)",
+            data.current_room,
+            data.display.displayed_index,
+            data.project
+                .rooms
+                .get(&data.current_room)
+                .map(|v| v.content.len())
+                .unwrap_or(0)
+        )));
+
+        lines.push(Line::default());
+        lines.push(heading("Visits"));
+        let mut visits: Vec<_> = data.visits.iter().collect();
+        visits.sort();
+        for (room, count) in visits {
+            lines.push(Line::from(format!("{} x{}", room, count)));
+        }
+
+        lines.push(Line::default());
+        lines.push(heading("Listeners"));
+        let mut listeners: Vec<_> = data.listeners.keyboard_char.iter().collect();
+        listeners.sort_by_key(|(id, _)| **id);
+        for (id, cb) in listeners {
+            lines.push(Line::from(format!(
+                "#{} keyboard/char {}",
+                id,
+                describe(cb)
+            )));
+        }
+
+        lines.push(Line::default());
+        lines.push(heading("Options"));
+        for (i, option) in data.options.options.iter().enumerate() {
+            lines.push(Line::from(format!(
+                "[{}] {} -> {}",
+                i,
+                option.name.to_raw(),
+                option.description
+            )));
+        }
+
+        lines
+    }
+
+    pub fn render(&self, frame: &mut Frame, environment: &Environment) {
+        let area = frame.area();
+        let width = (area.width / 2).max(20).min(area.width);
+        let area = Rect {
+            x: area.width - width,
+            width,
+            ..area
+        };
+        let panel = Paragraph::new(Self::lines(environment))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0))
+            .block(
+                Block::bordered()
+                    .border_set(border::DOUBLE)
+                    .title("Inspector")
+                    .title_bottom("F2 to close, PgUp/PgDn to scroll"),
+            );
+        frame.render_widget(Clear, area);
+        frame.render_widget(panel, area);
+    }
+}
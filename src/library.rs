@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    env, fs, io,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::{ProjectSource, ARCHIVE_EXTENSION},
+    environment::Environment,
+    errors::HHEError,
+    parser::{Metadata, MetadataInfo},
+};
+
+/// How many folders deep under a library root stories are looked for.
+const MAX_DEPTH: usize = 16;
+
+/// A story found in one of the library roots. Stories whose `meta.toml`
+/// couldn't be read are still listed, with the reason in `error`.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectDetails {
+    pub info: MetadataInfo,
+    pub path: String,
+    /// Contents of the file named by `cover`, if it could be read
+    pub cover: Option<String>,
+    pub error: Option<String>,
+}
+
+/// How far the player got in a story, kept between runs.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Progress {
+    /// Seconds since the unix epoch
+    pub last_played: u64,
+    pub room: String,
+    pub rooms_seen: usize,
+    /// Ending rooms reached in any playthrough
+    #[serde(default)]
+    pub endings: BTreeSet<String>,
+}
+
+/// User config read from `$XDG_CONFIG_HOME/hhe3/config.toml`
+/// (or `~/.config/hhe3/config.toml`).
+#[derive(Deserialize, Default, Debug)]
+struct UserConfig {
+    #[serde(default)]
+    library: Vec<String>,
+    /// Typing speed multiplier, see `reading_speed`
+    speed: Option<f64>,
+}
+
+/// The user config, or the defaults if there isn't one.
+fn user_config() -> Result<UserConfig> {
+    match config_path() {
+        Some(path) => read_config(&path),
+        None => Ok(UserConfig::default()),
+    }
+}
+
+fn read_config(path: &Path) -> Result<UserConfig> {
+    let bad = |e: String| HHEError::BadConfig(path.display().to_string(), e);
+    let content = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(UserConfig::default()),
+        Err(e) => return Err(bad(e.to_string()).into()),
+    };
+    Ok(toml::from_str(&content).map_err(|e| bad(e.to_string()))?)
+}
+
+fn config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("hhe3").join("config.toml"))
+}
+
+fn progress_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(base.join("hhe3").join("progress.json"))
+}
+
+/// Progress for every story played so far, keyed by the story's full path.
+pub fn load_progress() -> BTreeMap<String, Progress> {
+    progress_path()
+        .and_then(|v| fs::read_to_string(v).ok())
+        .and_then(|v| serde_json::from_str(&v).ok())
+        .unwrap_or_default()
+}
+
+/// Stores where the player is in the current story.
+pub fn record_progress(environment: &Environment) -> Result<()> {
+    let Some(path) = progress_path() else {
+        return Ok(());
+    };
+    let data = environment.data.read().unwrap();
+    let mut progress = load_progress();
+    let key = data.project.root.display().to_string();
+    let mut endings = progress.remove(&key).unwrap_or_default().endings;
+    endings.extend(data.endings.iter().cloned());
+    progress.insert(
+        key,
+        Progress {
+            last_played: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            room: data.current_room.clone(),
+            rooms_seen: data.visits.len(),
+            endings,
+        },
+    );
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&progress)?)?;
+    Ok(())
+}
+
+/// Folders to look for stories in: `--library` flags first, then the
+/// `HHE3_LIBRARY` variable, then the user config. Falls back to `./stories`.
+pub fn library_roots(flags: &[String]) -> Result<Vec<PathBuf>> {
+    let mut roots: Vec<PathBuf> = flags.iter().map(PathBuf::from).collect();
+    if let Some(paths) = env::var_os("HHE3_LIBRARY") {
+        roots.extend(env::split_paths(&paths).filter(|v| !v.as_os_str().is_empty()));
+    }
+    roots.extend(user_config()?.library.iter().map(PathBuf::from));
+    if roots.is_empty() {
+        roots.push(PathBuf::from("./stories"));
+    }
+    let mut unique = vec![];
+    for root in roots {
+        if !unique.contains(&root) {
+            unique.push(root);
+        }
+    }
+    Ok(unique)
+}
+
+/// Whether `path`, relative to a story's folder, stays inside it. Absolute
+/// paths and `..` are refused so a story can't show other files as its cover.
+fn inside_project(path: &Path) -> bool {
+    path.components()
+        .all(|v| matches!(v, Component::Normal(_) | Component::CurDir))
+}
+
+/// How much faster than written content is typed: the `--speed` flag, then
+/// `speed` in the user config, then 1. Anything not above 0 is ignored.
+pub fn reading_speed(flag: Option<f64>) -> Result<f64> {
+    let speed = match flag {
+        Some(v) => Some(v),
+        None => user_config()?.speed,
+    };
+    Ok(speed.filter(|v| *v > 0.0).unwrap_or(1.0))
+}
+
+fn details(path: &Path) -> ProjectDetails {
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    let source = ProjectSource::open(&path);
+    let meta = source
+        .as_ref()
+        .map_err(|e| e.to_string())
+        .and_then(|source| {
+            let content = source
+                .read_to_string(Path::new("meta.toml"))
+                .map_err(|e| e.to_string())?;
+            Metadata::from_toml(&content).map_err(|e| e.to_string())
+        });
+    match meta {
+        Ok(v) => ProjectDetails {
+            cover: v
+                .meta
+                .cover
+                .as_ref()
+                .filter(|cover| inside_project(Path::new(cover)))
+                .and_then(|cover| source.as_ref().ok()?.read_to_string(Path::new(cover)).ok()),
+            info: v.meta,
+            path: path.display().to_string(),
+            error: None,
+        },
+        Err(e) => ProjectDetails {
+            info: MetadataInfo {
+                name: path
+                    .file_name()
+                    .map(|v| v.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.display().to_string()),
+                ..Default::default()
+            },
+            path: path.display().to_string(),
+            cover: None,
+            error: Some(e),
+        },
+    }
+}
+
+/// Adds the stories at or under `dir`. `seen` holds the folders already
+/// searched, so symlinks pointing back up the tree are only followed once.
+fn discover(
+    dir: &Path,
+    depth: usize,
+    seen: &mut HashSet<PathBuf>,
+    projects: &mut Vec<ProjectDetails>,
+) {
+    if depth > MAX_DEPTH || !seen.insert(fs::canonicalize(dir).unwrap_or(dir.to_path_buf())) {
+        return;
+    }
+    if dir.join("meta.toml").is_file()
+        || (dir.is_file() && dir.extension().is_some_and(|v| v == ARCHIVE_EXTENSION))
+    {
+        projects.push(details(dir));
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut folders: Vec<PathBuf> = entries
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|v| v.is_dir() || v.extension().is_some_and(|v| v == ARCHIVE_EXTENSION))
+        .filter(|v| {
+            !v.file_name()
+                .is_some_and(|v| v.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    folders.sort();
+    for folder in folders {
+        discover(&folder, depth + 1, seen, projects);
+    }
+}
+
+/// Finds every folder containing a `meta.toml`, and every `.hhe` archive,
+/// under `roots` up to [`MAX_DEPTH`] folders deep.
+/// Unreadable folders are skipped.
+pub fn refresh_projects(roots: &[PathBuf], projects: &mut Vec<ProjectDetails>) {
+    projects.clear();
+    let mut seen = HashSet::new();
+    for root in roots {
+        discover(root, 0, &mut seen, projects);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("hhe3-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_searched_once() {
+        let root = temp_dir("library");
+        fs::create_dir_all(root.join("shelf/story")).unwrap();
+        fs::write(root.join("shelf/story/meta.toml"), "").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("shelf/loop")).unwrap();
+
+        let mut projects = vec![];
+        refresh_projects(&[root.clone(), root.join("shelf")], &mut projects);
+        assert_eq!(projects.len(), 1);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn bad_config_is_an_error() {
+        let dir = temp_dir("config");
+        assert!(read_config(&dir.join("missing.toml")).is_ok());
+        fs::write(dir.join("config.toml"), "library = 5").unwrap();
+        let error = read_config(&dir.join("config.toml")).unwrap_err();
+        assert!(error.to_string().starts_with("Couldn't read the config"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn covers_stay_inside_the_story() {
+        let root = temp_dir("cover");
+        fs::create_dir_all(root.join("story")).unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        fs::write(root.join("story/cover.txt"), "art").unwrap();
+        let cover = |cover: &str| {
+            let meta = format!(
+                "[settings]\nfirst_room = \"index\"\nrooms_folder = [\"rooms\"]\n\
+                 [meta]\nname = \"t\"\ncover = {:?}\n",
+                cover
+            );
+            fs::write(root.join("story/meta.toml"), meta).unwrap();
+            details(&root.join("story")).cover
+        };
+
+        assert_eq!(cover("cover.txt").as_deref(), Some("art"));
+        assert_eq!(cover("./cover.txt").as_deref(), Some("art"));
+        assert_eq!(cover("../secret.txt"), None);
+        let absolute = root.join("secret.txt").display().to_string();
+        assert_eq!(cover(&absolute), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -7,7 +7,7 @@ use rust_lisp::{
     utils::{require_arg, require_typed_arg},
 };
 
-use crate::environment::Container;
+use crate::environment::{container, Container};
 
 pub fn run_post(
     env: Rc<RefCell<Env>>,
@@ -20,17 +20,18 @@ pub fn run_post(
             .project
             .rooms
             .get(&outside.current_room)
-            .expect("Couldn't find room for current_room somehow?")
+            .ok_or_else(|| RuntimeError {
+                msg: format!("Couldn't find the room `{}`", outside.current_room),
+            })?
             .clone();
         room
     };
     let parsed = parse(&room.post);
     for root in parsed {
-        eval(
-            Rc::clone(&env),
-            &root.expect("Failed to parse lua in post section"),
-        )
-        .expect("Failed to evaluate post section");
+        let root = root.map_err(|e| RuntimeError {
+            msg: format!("Failed to parse post section: {}", e.msg),
+        })?;
+        eval(Rc::clone(&env), &root)?;
     }
     Ok(Value::NIL)
 }
@@ -135,7 +136,7 @@ pub mod string {
             "0" => "\0",
             _ => ch.as_str(),
         };
-        return Ok(Value::String(ch.to_string()));
+        Ok(Value::String(ch.to_string()))
     }
 }
 
@@ -147,7 +148,7 @@ pub fn room_set(
     let room = require_typed_arg::<&String>("room/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
 
-    outside.current_room = room.clone();
+    outside.current_room = outside.project.resolve_room(&outside.current_room, room);
 
     Ok(Value::NIL)
 }
@@ -162,28 +163,33 @@ pub fn room_get(
     Ok(Value::String(outside.current_room.clone()))
 }
 
-// pub fn import(
-//     env: Rc<RefCell<Env>>,
-//     args: Vec<Value>,
-//     _outside: Container,
-// ) -> Result<Value, RuntimeError> {
-//     let module: &Symbol = require_typed_arg("import", &args, 0)?;
-//     let source = match module.0.as_str() {
-//         "escape" => include_str!("./lisp_lib/escape.hh3"),
-//         "math" => include_str!("./lisp_lib/math.hh3"),
-//         "quick" => include_str!("./lisp_lib/quick.hh3"),
-//         "std" => include_str!("./lisp_lib/std.hh3"),
-//         _ => "",
-//     };
-
-//     let parsed: Result<Vec<Value>, _> = parse(source).collect();
-//     let parsed = parsed.unwrap();
-//     for root in parsed {
-//         eval(env.clone(), &root).expect("Failed to evaluate module");
-//     }
-
-//     Ok(Value::NIL)
-// }
+pub fn import(env: Rc<RefCell<Env>>, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let outside = container(&env)?;
+    let module: &Symbol = require_typed_arg("import", &args, 0)?;
+    let source = match module.0.as_str() {
+        "escape" => include_str!("../libs/escape.hh3").to_string(),
+        "math" => include_str!("../libs/math.hh3").to_string(),
+        "quick" => include_str!("../libs/quick.hh3").to_string(),
+        "std" => include_str!("../libs/std.hh3").to_string(),
+        name => match outside.read().unwrap().project.libs.get(name) {
+            Some(v) => v.clone(),
+            None => {
+                return Err(RuntimeError {
+                    msg: format!("No module called: {}", name),
+                })
+            }
+        },
+    };
+
+    for root in parse(&source) {
+        let root = root.map_err(|e| RuntimeError {
+            msg: format!("Failed to parse module {}: {}", module.0, e.msg),
+        })?;
+        eval(env.clone(), &root)?;
+    }
+
+    Ok(Value::NIL)
+}
 
 pub fn math_pow(
     _env: Rc<RefCell<Env>>,
@@ -193,7 +199,7 @@ pub fn math_pow(
     let base: f32 = require_typed_arg("math/pow", &args, 0)?;
     let exponent: f32 = require_typed_arg("math/pow", &args, 1)?;
 
-    return Ok(Value::Float(base.powf(exponent)));
+    Ok(Value::Float(base.powf(exponent)))
 }
 
 pub fn intrinsic(
@@ -233,5 +239,52 @@ pub fn to_string(
         Value::String(s) => s.to_string(),
         _ => value.type_name().to_string(),
     };
-    return Ok(Value::String(stringified));
+    Ok(Value::String(stringified))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::Environment;
+
+    fn environment(libs: &[(&str, &str)]) -> Environment {
+        let environment = Environment::new().register_all();
+        {
+            let mut data = environment.data.write().unwrap();
+            for (name, source) in libs {
+                data.project
+                    .libs
+                    .insert(name.to_string(), source.to_string());
+            }
+        }
+        environment
+    }
+
+    #[test]
+    fn nested_imports() {
+        let mut environment = environment(&[
+            // inside an `if`, so the import isn't a top level form of the module
+            (
+                "outer",
+                "(if true (import 'inner) nil) (define from-outer (+ from-inner 1))",
+            ),
+            ("inner", "(define from-inner 1)"),
+        ]);
+        environment.eval_source("(begin (import 'outer))").unwrap();
+        let value = environment.eval_source("from-outer").unwrap();
+        assert_eq!(value.to_string(), "2");
+
+        environment
+            .eval_source("(if (== from-outer 2) (import 'inner) nil)")
+            .unwrap();
+        environment
+            .eval_source("(defun load-all () (import 'outer)) (load-all)")
+            .unwrap();
+    }
+
+    #[test]
+    fn unknown_module() {
+        let mut environment = environment(&[("broken", "(import 'missing)")]);
+        let error = environment.eval_source("(import 'broken)").unwrap_err();
+        assert!(error.to_string().contains("No module called: missing"));
+    }
 }
@@ -0,0 +1,84 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rust_lisp::{
+    model::{Env, IntType, RuntimeError, Symbol, Value},
+    utils::{require_arg, require_typed_arg},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::environment::Container;
+
+use super::color::{deserialize_color, Color};
+
+/// Keys `character/define` takes, bound as `:key` symbols like [`super::style::STYLE_KEYS`].
+pub const CHARACTER_KEYS: &[&str] = &["name", "color", "text_color", "delay"];
+
+/// Someone who speaks in `@id: text` dialogue lines, from `[characters]` in
+/// `meta.toml` or `character/define`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Character {
+    /// Shown before their lines, their id if left out
+    pub name: Option<String>,
+    /// Colour of their name
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub color: Option<Color>,
+    /// Colour of what they say
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub text_color: Option<Color>,
+    /// Milliseconds per character while their lines are typed out
+    pub delay: Option<i64>,
+}
+
+pub fn character_define(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let id = require_typed_arg::<&Symbol>("character/define", &args, 0)?;
+    if args.len() % 2 != 1 {
+        return Err(RuntimeError {
+            msg: "character/define takes an id, then pairs of keys and values, e.g. :name \"Will\""
+                .to_string(),
+        });
+    }
+    let mut character = Character::default();
+    for i in (1..args.len()).step_by(2) {
+        let key = require_typed_arg::<&Symbol>("character/define", &args, i)?;
+        let value = require_arg("character/define", &args, i + 1)?;
+        let key = key.0.strip_prefix(':').unwrap_or(&key.0);
+        let color = || match value {
+            Value::Foreign(r) if r.is::<Color>() => Ok(*r.downcast_ref::<Color>().unwrap()),
+            v => Err(RuntimeError {
+                msg: format!(
+                    "character/define expected a colour for :{}, found {}",
+                    key, v
+                ),
+            }),
+        };
+        match key {
+            "name" => {
+                character.name =
+                    Some(require_typed_arg::<&String>("character/define", &args, i + 1)?.clone())
+            }
+            "color" => character.color = Some(color()?),
+            "text_color" => character.text_color = Some(color()?),
+            "delay" => {
+                character.delay =
+                    Some(require_typed_arg::<IntType>("character/define", &args, i + 1)? as i64)
+            }
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!(
+                        "character/define has no key :{}, expected one of :{}",
+                        key,
+                        CHARACTER_KEYS.join(", :")
+                    ),
+                })
+            }
+        }
+    }
+    let mut outside = outside.write().unwrap();
+    outside.characters.insert(id.0.clone(), character);
+    Ok(Value::NIL)
+}
@@ -4,10 +4,11 @@ use rust_lisp::{
     model::{Env, IntType, RuntimeError, Symbol, Value},
     utils::{require_typed_arg, TypeName},
 };
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
 use crate::environment::Container;
 
-#[derive(Debug, Clone, Default, Copy)]
+#[derive(Debug, Clone, Default, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Color(u8, u8, u8);
 
 impl TypeName for Color {
@@ -37,6 +38,77 @@ impl Color {
     pub fn to_ratatui_color(self) -> ratatui::style::Color {
         ratatui::style::Color::Rgb(self.0, self.1, self.2)
     }
+
+    /// Colours that can be used by name, e.g. `(color 'red)`.
+    pub fn named(name: &str) -> Option<Color> {
+        Some(match name {
+            "red" => Color(255, 0, 0),
+            "green" => Color(0, 255, 0),
+            "blue" => Color(0, 0, 255),
+            "black" => Color(0, 0, 0),
+            "white" => Color(255, 255, 255),
+            _ => return None,
+        })
+    }
+
+    /// The colour `t` of the way from `self` to `other`, with `t` from 0 to 1.
+    pub fn lerp(self, other: Color, t: f64) -> Color {
+        let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Color(
+            channel(self.0, other.0),
+            channel(self.1, other.1),
+            channel(self.2, other.2),
+        )
+    }
+
+    /// A fully saturated colour with the hue `hue`, in degrees.
+    pub fn hue(hue: f64) -> Color {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let x = ((1.0 - (h % 2.0 - 1.0).abs()) * 255.0).round() as u8;
+        match h as u8 {
+            0 => Color(255, x, 0),
+            1 => Color(x, 255, 0),
+            2 => Color(0, 255, x),
+            3 => Color(0, x, 255),
+            4 => Color(x, 0, 255),
+            _ => Color(255, 0, x),
+        }
+    }
+
+    /// Reads a colour name or a hex colour like `#00ff00`.
+    pub fn parse(text: &str) -> Option<Color> {
+        let Some(hex) = text.strip_prefix('#') else {
+            return Color::named(text);
+        };
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Color(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+/// A colour written in TOML, either as `[r, g, b]` or as text for `Color::parse`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TomlColor {
+    Rgb(u8, u8, u8),
+    Text(String),
+}
+
+/// For `Option<Color>` fields in TOML files, with `#[serde(default)]`.
+pub fn deserialize_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Color>, D::Error> {
+    match TomlColor::deserialize(deserializer)? {
+        TomlColor::Rgb(r, g, b) => Ok(Some(Color(r, g, b))),
+        TomlColor::Text(text) => Color::parse(&text).map(Some).ok_or_else(|| {
+            D::Error::custom(format!(
+                "no colour called `{}`, use a name like \"red\", \"#ff0000\" or [255, 0, 0]",
+                text
+            ))
+        }),
+    }
 }
 
 pub fn color_new(
@@ -61,7 +133,7 @@ pub fn color_new(
         Err(e) => return Err(RuntimeError { msg: e.to_string() }),
     };
 
-    return Ok(Value::Foreign(Rc::new(Color(r, g, b))));
+    Ok(Value::Foreign(Rc::new(Color(r, g, b))))
 }
 pub fn color(
     _env: Rc<RefCell<Env>>,
@@ -70,18 +142,14 @@ pub fn color(
 ) -> Result<Value, RuntimeError> {
     let color = require_typed_arg::<&Symbol>("color", &args, 0)?;
 
-    let color = match color.0.as_str() {
-        "red" => Color(255, 0, 0),
-        "green" => Color(0, 255, 0),
-        "blue" => Color(0, 0, 255),
-        "black" => Color(0, 0, 0),
-        "white" => Color(255, 255, 255),
-        _ => {
+    let color = match Color::named(&color.0) {
+        Some(v) => v,
+        None => {
             return Err(RuntimeError {
                 msg: format!("No color called: {}", color.0),
             })
         }
     };
 
-    return Ok(Value::Foreign(Rc::new(color)));
+    Ok(Value::Foreign(Rc::new(color)))
 }
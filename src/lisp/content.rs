@@ -1,11 +1,11 @@
 use std::{cell::RefCell, rc::Rc, sync::Arc};
 
 use rust_lisp::{
-    model::{Env, IntType, RuntimeError, Value},
+    model::{Env, IntType, RuntimeError, Symbol, Value},
     utils::require_typed_arg,
 };
 
-use crate::environment::{Container, ContentChar};
+use crate::environment::{ColorTarget, Container, ContentChar, Effect, RevealMode};
 
 use super::color::Color;
 
@@ -17,7 +17,46 @@ pub fn set_delay(
     let delay = require_typed_arg::<IntType>("delay/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
     outside.display.delay = delay as i64;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
+}
+
+pub fn set_delay_mode(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let mode = require_typed_arg::<&Symbol>("delay/mode", &args, 0)?;
+    let mode = RevealMode::from_name(&mode.0).ok_or(RuntimeError {
+        msg: format!(
+            "No reveal mode called {}, expected one of {}",
+            mode.0,
+            RevealMode::NAMES.join(", ")
+        ),
+    })?;
+    let mut outside = outside.write().unwrap();
+    outside.display.reveal = mode;
+    Ok(Value::NIL)
+}
+
+pub fn wait(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let ms = require_typed_arg::<IntType>("wait", &args, 0)?;
+    let mut outside = outside.write().unwrap();
+    outside.display.wait_ms = (ms as i64).max(0);
+    Ok(Value::NIL)
+}
+
+pub fn wait_key(
+    _env: Rc<RefCell<Env>>,
+    _args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let mut outside = outside.write().unwrap();
+    outside.display.wait_key = true;
+    Ok(Value::NIL)
 }
 
 pub fn set_content_fg(
@@ -28,7 +67,7 @@ pub fn set_content_fg(
     let color = require_typed_arg::<Color>("fg/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
     outside.display.current_fg = color;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn set_content_bg(
     _env: Rc<RefCell<Env>>,
@@ -38,7 +77,7 @@ pub fn set_content_bg(
     let color = require_typed_arg::<Color>("bg/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
     outside.display.current_bg = color;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn set_fg(
     _env: Rc<RefCell<Env>>,
@@ -47,8 +86,19 @@ pub fn set_fg(
 ) -> Result<Value, RuntimeError> {
     let color = require_typed_arg::<Color>("display/fg/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
-    outside.display.display_fg = color;
-    return Ok(Value::NIL);
+    outside.set_color(ColorTarget::DisplayFg, color);
+    Ok(Value::NIL)
+}
+pub fn tween_fg(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let color = require_typed_arg::<Color>("display/fg/tween", &args, 0)?;
+    let ms = require_typed_arg::<IntType>("display/fg/tween", &args, 1)?;
+    let mut outside = outside.write().unwrap();
+    outside.tween_color(ColorTarget::DisplayFg, color, ms as i64);
+    Ok(Value::NIL)
 }
 pub fn get_fg(
     _env: Rc<RefCell<Env>>,
@@ -56,7 +106,7 @@ pub fn get_fg(
     outside: Container,
 ) -> Result<Value, RuntimeError> {
     let outside = outside.read().unwrap();
-    return Ok(Value::Foreign(Rc::new(outside.display.display_fg)));
+    Ok(Value::Foreign(Rc::new(outside.display.display_fg)))
 }
 pub fn set_bg(
     _env: Rc<RefCell<Env>>,
@@ -65,8 +115,19 @@ pub fn set_bg(
 ) -> Result<Value, RuntimeError> {
     let color = require_typed_arg::<Color>("display/bg/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
-    outside.display.display_bg = color;
-    return Ok(Value::NIL);
+    outside.set_color(ColorTarget::DisplayBg, color);
+    Ok(Value::NIL)
+}
+pub fn tween_bg(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let color = require_typed_arg::<Color>("display/bg/tween", &args, 0)?;
+    let ms = require_typed_arg::<IntType>("display/bg/tween", &args, 1)?;
+    let mut outside = outside.write().unwrap();
+    outside.tween_color(ColorTarget::DisplayBg, color, ms as i64);
+    Ok(Value::NIL)
 }
 pub fn get_bg(
     _env: Rc<RefCell<Env>>,
@@ -74,7 +135,7 @@ pub fn get_bg(
     outside: Container,
 ) -> Result<Value, RuntimeError> {
     let outside = outside.read().unwrap();
-    return Ok(Value::Foreign(Rc::new(outside.display.display_bg)));
+    Ok(Value::Foreign(Rc::new(outside.display.display_bg)))
 }
 pub fn set_ac(
     _env: Rc<RefCell<Env>>,
@@ -83,8 +144,19 @@ pub fn set_ac(
 ) -> Result<Value, RuntimeError> {
     let color = require_typed_arg::<Color>("display/ac/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
-    outside.display.display_ac = color;
-    return Ok(Value::NIL);
+    outside.set_color(ColorTarget::DisplayAc, color);
+    Ok(Value::NIL)
+}
+pub fn tween_ac(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let color = require_typed_arg::<Color>("display/ac/tween", &args, 0)?;
+    let ms = require_typed_arg::<IntType>("display/ac/tween", &args, 1)?;
+    let mut outside = outside.write().unwrap();
+    outside.tween_color(ColorTarget::DisplayAc, color, ms as i64);
+    Ok(Value::NIL)
 }
 pub fn get_ac(
     _env: Rc<RefCell<Env>>,
@@ -92,7 +164,18 @@ pub fn get_ac(
     outside: Container,
 ) -> Result<Value, RuntimeError> {
     let outside = outside.read().unwrap();
-    return Ok(Value::Foreign(Rc::new(outside.display.display_ac)));
+    Ok(Value::Foreign(Rc::new(outside.display.display_ac)))
+}
+pub fn flash(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let color = require_typed_arg::<Color>("display/flash", &args, 0)?;
+    let ms = require_typed_arg::<IntType>("display/flash", &args, 1)?;
+    let mut outside = outside.write().unwrap();
+    outside.flash_color(ColorTarget::DisplayBg, color, ms as i64);
+    Ok(Value::NIL)
 }
 pub fn bold(
     _env: Rc<RefCell<Env>>,
@@ -101,7 +184,7 @@ pub fn bold(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.display.bold = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn italic(
     _env: Rc<RefCell<Env>>,
@@ -110,7 +193,7 @@ pub fn italic(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.display.italic = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn crossed(
     _env: Rc<RefCell<Env>>,
@@ -119,7 +202,7 @@ pub fn crossed(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.display.crossed = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn underline(
     _env: Rc<RefCell<Env>>,
@@ -128,7 +211,7 @@ pub fn underline(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.display.underline = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn reset(
     _env: Rc<RefCell<Env>>,
@@ -142,7 +225,25 @@ pub fn reset(
     outside.display.underline = false;
     outside.display.current_fg = Color::default();
     outside.display.current_bg = Color::default();
-    return Ok(Value::NIL);
+    outside.display.effect = Effect::None;
+    Ok(Value::NIL)
+}
+pub fn set_effect(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let effect = require_typed_arg::<&Symbol>("effect", &args, 0)?;
+    let effect = Effect::from_name(&effect.0).ok_or(RuntimeError {
+        msg: format!(
+            "No effect called {}, expected one of {}",
+            effect.0,
+            Effect::NAMES.join(", ")
+        ),
+    })?;
+    let mut outside = outside.write().unwrap();
+    outside.display.effect = effect;
+    Ok(Value::NIL)
 }
 
 pub fn content_clear(
@@ -152,7 +253,7 @@ pub fn content_clear(
 ) -> Result<Value, RuntimeError> {
     let outside = Arc::clone(&outside);
     let mut write = outside.write().unwrap();
-    write.display.content.0.clear();
+    write.display.clear();
     write.display.displayed_index = 0;
 
     Ok(Value::NIL)
@@ -17,7 +17,7 @@ pub fn keyboard_char_listener(
     let mut outside = outside.write().unwrap();
     let i = outside.listeners.keyboard_char.len();
     outside.listeners.keyboard_char.insert(i as i32, cb.clone());
-    return Ok(Value::Int(i as IntType));
+    Ok(Value::Int(i as IntType))
 }
 
 pub fn listener_clear(
@@ -29,5 +29,5 @@ pub fn listener_clear(
     let outside = Arc::clone(&outside);
     let mut outside = outside.write().unwrap();
     outside.listeners.keyboard_char.remove(&(cb as i32));
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
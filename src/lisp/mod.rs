@@ -1,7 +1,9 @@
 pub mod basic;
+pub mod character;
 pub mod color;
 pub mod content;
 pub mod listener;
 pub mod option;
 pub mod random;
+pub mod style;
 pub mod title;
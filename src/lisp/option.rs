@@ -6,7 +6,23 @@ use rust_lisp::{
     utils::{require_arg, require_typed_arg},
 };
 
-use crate::environment::{Container, Content, OptionDataSingle};
+use crate::environment::{Container, Content, EnvData, OptionDataSingle};
+
+use super::style::Style;
+
+/// The label of an option, in the current style or the one given as the
+/// option's third argument.
+fn label(func: &str, name: &str, args: &[Value], data: &EnvData) -> Result<Content, RuntimeError> {
+    let style = match args.get(2) {
+        Some(v) => Style::from_arg(func, v, data)?,
+        None => Style::default(),
+    };
+    Ok(Content(
+        name.chars()
+            .map(|v| data.display.to_styled_char(v, &style))
+            .collect(),
+    ))
+}
 
 pub fn option_reset(
     _env: Rc<RefCell<Env>>,
@@ -31,17 +47,16 @@ pub fn option_goto(
     let mut out = outside_ar.write().unwrap();
 
     let name = require_typed_arg::<&String>("option/goto", &args, 0)?;
-    let next_room = require_typed_arg::<&Symbol>("option/goto", &args, 1)?.clone();
+    let next_room = require_typed_arg::<&Symbol>("option/goto", &args, 1)?;
+    let next_room = Symbol(out.project.resolve_room(&out.current_room, &next_room.0));
 
-    let content = Content(
-        name.chars()
-            .map(|v| out.display.to_content_char(v))
-            .collect(),
-    );
+    let content = label("option/goto", name, &args, &out)?;
 
     let outside_ar = Arc::clone(&outside);
+    let description = format!("goto {}", next_room.0);
     out.options.options.push(OptionDataSingle {
         name: content,
+        description,
         action: Value::NativeClosure(Rc::new(RefCell::new(move |_env, _args| {
             let outside_ar = Arc::clone(&outside_ar);
             outside_ar.write().unwrap().current_room = next_room.0.clone();
@@ -63,14 +78,11 @@ pub fn option_action(
     let name = require_typed_arg::<&String>("option/action", &args, 0)?;
     let action = require_arg("option/action", &args, 1)?.clone();
 
-    let content = Content(
-        name.chars()
-            .map(|v| out.display.to_content_char(v))
-            .collect(),
-    );
+    let content = label("option/action", name, &args, &out)?;
 
     out.options.options.push(OptionDataSingle {
         name: content,
+        description: action.to_string(),
         action,
     });
 
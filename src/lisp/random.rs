@@ -16,5 +16,5 @@ pub fn random_int(
     let a: IntType = require_typed_arg("random/int", &args, 0)?;
     let b: IntType = require_typed_arg("random/int", &args, 1)?;
 
-    return Ok(Value::Int(rand::thread_rng().gen_range(a..b)));
+    Ok(Value::Int(rand::thread_rng().gen_range(a..b)))
 }
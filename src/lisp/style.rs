@@ -0,0 +1,165 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rust_lisp::{
+    model::{Env, RuntimeError, Symbol, Value},
+    utils::{require_arg, require_typed_arg},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::environment::{Container, EnvData};
+
+use super::color::{deserialize_color, Color};
+
+/// Keys `style/new` takes. Each is also bound as a `:key` symbol, so
+/// `(style/new :fg (color 'red))` works without quoting.
+pub const STYLE_KEYS: &[&str] = &["fg", "bg", "bold", "italic", "crossed", "underline"];
+
+/// Content styles as a value, made with `style/new` or named in `[styles]` in
+/// `meta.toml`. Anything left as `None` is kept as it was when the style is
+/// applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Style {
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub fg: Option<Color>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub bg: Option<Color>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub crossed: Option<bool>,
+    pub underline: Option<bool>,
+}
+
+impl Style {
+    /// Reads a style value made by `style/new` or `style/get`.
+    pub fn from_value(func: &str, value: &Value) -> Result<Style, RuntimeError> {
+        match value {
+            Value::Foreign(r) if r.is::<Style>() => Ok(*r.downcast_ref::<Style>().unwrap()),
+            v => Err(RuntimeError {
+                msg: format!("{} expected a style, found {}", func, v),
+            }),
+        }
+    }
+
+    /// Reads a style value, or the name of a style from `style/define` or
+    /// `[styles]`.
+    pub fn from_arg(func: &str, value: &Value, data: &EnvData) -> Result<Style, RuntimeError> {
+        match value {
+            Value::Symbol(name) => data.named_style(&name.0).ok_or(RuntimeError {
+                msg: format!("{}: no style called {}", func, name.0),
+            }),
+            v => Style::from_value(func, v),
+        }
+    }
+}
+
+pub fn style_new(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    _outside: Container,
+) -> Result<Value, RuntimeError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(RuntimeError {
+            msg: "style/new takes pairs of keys and values, e.g. :bold true".to_string(),
+        });
+    }
+    let mut style = Style::default();
+    for i in (0..args.len()).step_by(2) {
+        let key = require_typed_arg::<&Symbol>("style/new", &args, i)?;
+        let value = require_arg("style/new", &args, i + 1)?;
+        let key = key.0.strip_prefix(':').unwrap_or(&key.0);
+        let color = || match value {
+            Value::Foreign(r) if r.is::<Color>() => Ok(*r.downcast_ref::<Color>().unwrap()),
+            v => Err(RuntimeError {
+                msg: format!("style/new expected a colour for :{}, found {}", key, v),
+            }),
+        };
+        let flag = Some(value.into());
+        match key {
+            "fg" => style.fg = Some(color()?),
+            "bg" => style.bg = Some(color()?),
+            "bold" => style.bold = flag,
+            "italic" => style.italic = flag,
+            "crossed" => style.crossed = flag,
+            "underline" => style.underline = flag,
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!(
+                        "style/new has no key :{}, expected one of :{}",
+                        key,
+                        STYLE_KEYS.join(", :")
+                    ),
+                })
+            }
+        }
+    }
+    Ok(Value::Foreign(Rc::new(style)))
+}
+
+pub fn style_get(
+    _env: Rc<RefCell<Env>>,
+    _args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let outside = outside.read().unwrap();
+    Ok(Value::Foreign(Rc::new(outside.display.style())))
+}
+
+pub fn style_apply(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let mut outside = outside.write().unwrap();
+    let style = Style::from_arg(
+        "style/apply",
+        require_arg("style/apply", &args, 0)?,
+        &outside,
+    )?;
+    outside.display.apply_style(&style);
+    Ok(Value::NIL)
+}
+
+pub fn style_define(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let name = require_typed_arg::<&Symbol>("style/define", &args, 0)?;
+    let style = Style::from_value("style/define", require_arg("style/define", &args, 1)?)?;
+    let mut outside = outside.write().unwrap();
+    outside.styles.insert(name.0.clone(), style);
+    Ok(Value::NIL)
+}
+
+pub fn style_push(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let mut outside = outside.write().unwrap();
+    let style = match args.first() {
+        Some(v) => Some(Style::from_arg("style/push", v, &outside)?),
+        None => None,
+    };
+    let current = outside.display.style();
+    outside.display.style_stack.push(current);
+    if let Some(style) = style {
+        outside.display.apply_style(&style);
+    }
+    Ok(Value::NIL)
+}
+
+pub fn style_pop(
+    _env: Rc<RefCell<Env>>,
+    _args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let mut outside = outside.write().unwrap();
+    let style = outside.display.style_stack.pop().ok_or(RuntimeError {
+        msg: "style/pop without a style/push".to_string(),
+    })?;
+    outside.display.apply_style(&style);
+    Ok(Value::NIL)
+}
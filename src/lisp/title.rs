@@ -1,11 +1,11 @@
 use std::{cell::RefCell, rc::Rc};
 
 use rust_lisp::{
-    model::{Env, RuntimeError, Value},
+    model::{Env, IntType, RuntimeError, Value},
     utils::{require_arg, require_typed_arg},
 };
 
-use crate::environment::Container;
+use crate::environment::{ColorTarget, Container};
 
 use super::color::Color;
 
@@ -17,7 +17,7 @@ pub fn set_name(
     let name = require_typed_arg::<&String>("title/name/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
     outside.title.content = name.to_string();
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn set_fg(
     _env: Rc<RefCell<Env>>,
@@ -26,8 +26,19 @@ pub fn set_fg(
 ) -> Result<Value, RuntimeError> {
     let color = require_typed_arg::<Color>("title/fg/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
-    outside.title.fg = color;
-    return Ok(Value::NIL);
+    outside.set_color(ColorTarget::TitleFg, color);
+    Ok(Value::NIL)
+}
+pub fn tween_fg(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let color = require_typed_arg::<Color>("title/fg/tween", &args, 0)?;
+    let ms = require_typed_arg::<IntType>("title/fg/tween", &args, 1)?;
+    let mut outside = outside.write().unwrap();
+    outside.tween_color(ColorTarget::TitleFg, color, ms as i64);
+    Ok(Value::NIL)
 }
 pub fn set_bg(
     _env: Rc<RefCell<Env>>,
@@ -36,8 +47,19 @@ pub fn set_bg(
 ) -> Result<Value, RuntimeError> {
     let color = require_typed_arg::<Color>("title/bg/set", &args, 0)?;
     let mut outside = outside.write().unwrap();
-    outside.title.bg = color;
-    return Ok(Value::NIL);
+    outside.set_color(ColorTarget::TitleBg, color);
+    Ok(Value::NIL)
+}
+pub fn tween_bg(
+    _env: Rc<RefCell<Env>>,
+    args: Vec<Value>,
+    outside: Container,
+) -> Result<Value, RuntimeError> {
+    let color = require_typed_arg::<Color>("title/bg/tween", &args, 0)?;
+    let ms = require_typed_arg::<IntType>("title/bg/tween", &args, 1)?;
+    let mut outside = outside.write().unwrap();
+    outside.tween_color(ColorTarget::TitleBg, color, ms as i64);
+    Ok(Value::NIL)
 }
 pub fn bold(
     _env: Rc<RefCell<Env>>,
@@ -46,7 +68,7 @@ pub fn bold(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.title.bold = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn italic(
     _env: Rc<RefCell<Env>>,
@@ -55,7 +77,7 @@ pub fn italic(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.title.italic = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn crossed(
     _env: Rc<RefCell<Env>>,
@@ -64,7 +86,7 @@ pub fn crossed(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.title.crossed = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn underline(
     _env: Rc<RefCell<Env>>,
@@ -73,7 +95,7 @@ pub fn underline(
 ) -> Result<Value, RuntimeError> {
     let mut outside = outside.write().unwrap();
     outside.title.underline = true;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn reset(
     _env: Rc<RefCell<Env>>,
@@ -85,7 +107,7 @@ pub fn reset(
     outside.title.italic = false;
     outside.title.crossed = false;
     outside.title.underline = false;
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
 pub fn show(
     _env: Rc<RefCell<Env>>,
@@ -101,5 +123,5 @@ pub fn show(
         Value::Float(n) => *n > 0.,
         _ => false,
     };
-    return Ok(Value::NIL);
+    Ok(Value::NIL)
 }
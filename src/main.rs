@@ -1,53 +1,103 @@
+pub mod archive;
+pub mod console;
 pub mod environment;
 pub mod errors;
+pub mod inspector;
+pub mod library;
 pub mod lisp;
 pub mod parser;
+pub mod picker;
 pub mod project;
+pub mod repl;
+pub mod save;
+pub mod server;
+pub mod stdio;
 
-use std::{
-    fs,
-    io::{stdout, Write},
-    process,
-    rc::Rc,
-    time::Duration,
-};
+use std::{io::stdout, panic, path::PathBuf, time::Duration};
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use console::Console;
 use crossterm::{
-    event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind},
-    terminal::{Clear, ClearType},
-    ExecutableCommand,
+    cursor::Show,
+    event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use environment::Environment;
-use parser::{Metadata, ProjectParser};
+use inspector::Inspector;
+use library::{library_roots, reading_speed, record_progress};
+use parser::ProjectParser;
+use picker::{Picker, PickerAction};
 use ratatui::{
     layout::{Constraint, Direction, Layout},
     prelude::CrosstermBackend,
     style::{Modifier, Style, Styled, Stylize},
     symbols::{self, border},
-    text::Text,
+    text::{Line, Span, Text},
     widgets::{Block, List, ListDirection, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
-use rust_lisp::{interpreter::eval, lisp, model::Value, parser::parse};
 
 #[derive(Parser, Debug)]
 struct Args {
     #[command(subcommand)]
     subcommand: Option<Action>,
+    /// Enable developer tools such as the console (toggled with `)
+    #[arg(long, global = true)]
+    dev: bool,
+    /// Folder to look for stories in, searched recursively. Can be repeated
+    #[arg(long, global = true, value_name = "PATH")]
+    library: Vec<String>,
+    /// Type content this many times faster, e.g. 2 or 0.5
+    #[arg(long, global = true)]
+    speed: Option<f64>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Action {
-    Run { project_root: String },
+    /// Play a story
+    Run {
+        project_root: String,
+        /// Room to start in, after the first room has set the story up
+        #[arg(long)]
+        room: Option<String>,
+        /// Define a variable before playing, e.g. `--set key=true`. The value is lisp
+        #[arg(long, value_name = "NAME=VALUE")]
+        set: Vec<String>,
+    },
+    /// Play a story over stdin/stdout using JSON lines
+    ServeStdio {
+        project_root: String,
+        /// Only let time pass on `tick` commands, so runs can be replayed exactly
+        #[arg(long)]
+        ticks: bool,
+    },
+    /// Evaluate lisp interactively with every engine builtin loaded
+    Repl { project_root: Option<String> },
+    /// Pack a story folder into a single .hhe file for sharing
+    Pack {
+        project_root: String,
+        /// Where to write the archive, defaults to <folder name>.hhe
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Host a story for telnet clients, one session per connection
+    Serve {
+        project_root: String,
+        #[arg(long, default_value_t = 2323)]
+        port: u16,
+        #[arg(long, default_value = "0.0.0.0")]
+        bind: String,
+    },
 }
 
 #[derive(Clone, Debug)]
 enum TuiState {
     Menu { selection: ListState },
-    Folders { selection: ListState },
+    Folders,
     Story,
+    Quit,
 }
 
 fn menu_render(frame: &mut Frame, state: &mut TuiState, selection: &ListState) {
@@ -73,9 +123,9 @@ fn menu_render(frame: &mut Frame, state: &mut TuiState, selection: &ListState) {
     *state = TuiState::Menu { selection };
 }
 
-fn menu_input(state: &mut TuiState, selection: &ListState) -> Result<()> {
-    match read()? {
-        Event::Key(ev) => match ev {
+fn menu_input(ev: Event, state: &mut TuiState, selection: &ListState) -> Result<()> {
+    if let Event::Key(ev) = ev {
+        match ev {
             KeyEvent {
                 code: KeyCode::Char('j'),
                 kind: KeyEventKind::Press,
@@ -100,249 +150,231 @@ fn menu_input(state: &mut TuiState, selection: &ListState) -> Result<()> {
                 kind: KeyEventKind::Press,
                 ..
             } => match selection.selected() {
-                Some(0) => {
-                    *state = TuiState::Folders {
-                        selection: ListState::default(),
-                    }
-                }
+                Some(0) => *state = TuiState::Folders,
                 Some(1) => {}
                 Some(2) => {
-                    process::exit(0);
+                    *state = TuiState::Quit;
                 }
                 _ => {}
             },
 
             _ => {}
-        },
-        _ => {}
-    };
+        }
+    }
     Ok(())
 }
 
-fn folder_input(
-    state: &mut TuiState,
-    projects: &Vec<ProjectDetails>,
-    selection: &ListState,
-    environment: &mut Environment,
-) -> Result<()> {
-    match read()? {
-        Event::Key(ev) => match ev {
-            KeyEvent {
-                code: KeyCode::Char('j'),
-                kind: KeyEventKind::Press,
-                ..
-            } => {
-                let mut selection = selection.clone();
-                selection.select_next();
-
-                *state = TuiState::Folders { selection };
-            }
-            KeyEvent {
-                code: KeyCode::Char('k'),
-                kind: KeyEventKind::Press,
-                ..
-            } => {
-                let mut selection = selection.clone();
-                selection.select_previous();
-                *state = TuiState::Folders { selection };
-            }
-            KeyEvent {
-                code: KeyCode::Enter,
-                kind: KeyEventKind::Press,
-                ..
-            } => {
-                if selection.selected().is_some() {
-                    let p = &projects[selection.selected().unwrap()];
-                    let mut parser = ProjectParser::new(&p.path);
-                    let project = parser.parse().unwrap();
-                    {
-                        let mut write = environment.data.write().unwrap();
-                        write.project = project.clone();
-                        write.current_room = project.meta.settings.first_room;
-                    };
-                    *state = TuiState::Story;
-                }
-            }
-
-            KeyEvent {
-                code: KeyCode::Esc | KeyCode::Char('q'),
-                kind: KeyEventKind::Press,
-                ..
-            } => {
-                *state = TuiState::Menu {
-                    selection: ListState::default(),
-                }
-            }
+fn main() -> Result<()> {
+    let args = Args::parse();
 
-            _ => {}
-        },
-        _ => {}
-    };
+    match args.subcommand {
+        Some(subc) => cli(subc, args.dev, &args.library, args.speed)?,
+        None => {
+            let state = TuiState::Menu {
+                selection: ListState::default(),
+            };
+            let mut environment = Environment::new().register_all();
+            environment.speed = reading_speed(args.speed)?;
+            tui(state, environment, args.dev, &library_roots(&args.library)?)?;
+        }
+    }
     Ok(())
 }
-struct ProjectDetails {
-    name: String,
-    author: Option<String>,
-    path: String,
+
+/// Puts the terminal into raw mode on the alternate screen, and puts it back
+/// when dropped so quitting (or crashing) leaves the shell usable.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> Result<TerminalGuard> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen)?;
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+        Ok(TerminalGuard)
+    }
 }
 
-fn refresh_projects(projects: &mut Vec<ProjectDetails>) {
-    projects.clear();
-    if fs::exists("./stories").unwrap() {
-        let dir = fs::read_dir("./stories").unwrap();
-        for folder in dir {
-            let path = folder.unwrap().path();
-            let content = fs::read_to_string(path.clone().join("meta.toml"));
-            match content {
-                Ok(v) => {
-                    let v: Metadata = toml::from_str(&v).unwrap();
-                    projects.push(ProjectDetails {
-                        name: v.meta.name,
-                        author: v.meta.author,
-                        path: path.to_str().unwrap().to_string(),
-                    });
-                }
-                Err(_) => {}
-            }
-        }
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
     }
 }
 
-fn main() -> Result<()> {
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(stdout(), LeaveAlternateScreen, Show);
+}
+
+fn tui(
+    mut state: TuiState,
+    mut environment: Environment,
+    dev: bool,
+    roots: &[PathBuf],
+) -> Result<()> {
+    let _guard = TerminalGuard::new()?;
     let backend = CrosstermBackend::new(stdout());
     let mut terminal = Terminal::new(backend)?;
-    let args = Args::parse();
-
-    let mut state = TuiState::Menu {
-        selection: ListState::default(),
-    };
-
-    let mut projects = vec![];
+    terminal.clear()?;
 
-    refresh_projects(&mut projects);
+    let mut picker = Picker::new(roots);
+    let mut recorded_room = String::new();
 
-    stdout().execute(Clear(ClearType::All))?.flush()?;
-
-    match args.subcommand {
-        Some(subc) => cli(subc)?,
-        None => {
-            let mut environment = Environment::new().register_all();
-            loop {
-                terminal.draw(|frame| match state.clone() {
-                    TuiState::Menu { selection } => {
-                        menu_render(frame, &mut state, &selection.clone());
-                    }
-                    TuiState::Folders { selection } => {
-                        let mut sel = selection.clone();
-                        folders_render(frame, &mut state, &mut sel, &projects).unwrap();
-                        state = TuiState::Folders { selection: sel };
-                    }
-                    TuiState::Story => {
-                        story_render(frame, &mut environment).unwrap();
+    let mut console = Console::default();
+    let mut inspector = Inspector::default();
+    loop {
+        terminal.draw(|frame| match state.clone() {
+            TuiState::Menu { selection } => {
+                menu_render(frame, &mut state, &selection.clone());
+            }
+            TuiState::Folders => picker.render(frame),
+            TuiState::Quit => {}
+            TuiState::Story => {
+                story_render(frame, &mut environment).unwrap();
+                if inspector.open {
+                    inspector.render(frame, &environment);
+                }
+                if console.open {
+                    console.render(frame, &environment);
+                }
+            }
+        })?;
+        if poll(Duration::from_millis(2))? {
+            let ev = read()?;
+            // raw mode swallows Ctrl-C, so it has to be handled here
+            if let Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }) = ev
+            {
+                break;
+            }
+            match state.clone() {
+                TuiState::Menu { ref selection } => menu_input(ev, &mut state, selection)?,
+                TuiState::Folders => match picker.input(&ev) {
+                    PickerAction::None => {}
+                    PickerAction::Back => {
+                        state = TuiState::Menu {
+                            selection: ListState::default(),
+                        }
                     }
-                })?;
-                if poll(Duration::from_millis(2))? {
-                    match state.clone() {
-                        TuiState::Menu { ref selection } => menu_input(&mut state, selection)?,
-                        TuiState::Folders { ref selection } => {
-                            folder_input(&mut state, &projects, selection, &mut environment)?
+                    PickerAction::Open(path) => match ProjectParser::new(&path).parse() {
+                        Ok(project) => {
+                            let mut write = environment.data.write().unwrap();
+                            write.current_room = project.meta.settings.first_room.clone();
+                            write.project = project;
+                            state = TuiState::Story;
+                        }
+                        Err(e) => picker.set_error(&path, e.to_string()),
+                    },
+                },
+                TuiState::Quit => {}
+                TuiState::Story => {
+                    let dev = dev || {
+                        let read = environment.data.read().unwrap();
+                        read.project.meta.settings.dev
+                    };
+                    let used =
+                        dev && (console.input(&ev, &mut environment) || inspector.input(&ev));
+                    if !used {
+                        if let Err(e) = story_input(ev, &mut environment) {
+                            environment.report(e);
                         }
-                        TuiState::Story { .. } => story_input(read()?, &mut environment)?,
                     }
                 }
+            }
+        }
 
-                environment.update();
-                if environment.data.read().unwrap().quit {
-                    break;
+        if let Err(e) = environment.update() {
+            environment.report(e);
+        }
+        if matches!(state, TuiState::Story) {
+            let room = environment.data.read().unwrap().current_room.clone();
+            if room != recorded_room {
+                recorded_room = room;
+                if let Err(e) = record_progress(&environment) {
+                    environment.report(e);
                 }
             }
         }
+        if matches!(state, TuiState::Quit) || environment.data.read().unwrap().quit {
+            break;
+        }
     }
     Ok(())
 }
 
 fn story_input(ev: Event, environment: &mut Environment) -> Result<()> {
-    match ev {
-        Event::Key(key) => {
-            match key {
-                KeyEvent {
-                    code: KeyCode::Down | KeyCode::Up,
-                    kind: KeyEventKind::Press,
-                    ..
-                } => {
-                    let mut write = environment.data.write().unwrap();
-                    if write.options.options.len() > 0 {
-                        if let KeyEvent {
-                            code: KeyCode::Down,
-                            ..
-                        } = key
-                        {
-                            let len = write.options.options.len();
-                            if len != 0 && write.options.selected.selected().is_none() {
-                                write.options.selected.select(Some(0));
-                            } else if let Some(v) = write.options.selected.selected() {
-                                write.options.selected.select(Some((v + len + 1) % len));
-                            }
-                        } else {
-                            let len = write.options.options.len();
-                            if let Some(v) = write.options.selected.selected() {
-                                write.options.selected.select(Some((v + len - 1) % len));
-                            }
+    if let Event::Key(key) = ev {
+        // Enter or Space carries on after `(wait-key)`
+        if let KeyEvent {
+            code: KeyCode::Enter | KeyCode::Char(' '),
+            kind: KeyEventKind::Press,
+            ..
+        } = key
+        {
+            if environment.continue_reading() {
+                return Ok(());
+            }
+        }
+        match key {
+            KeyEvent {
+                code: KeyCode::Down | KeyCode::Up,
+                kind: KeyEventKind::Press,
+                ..
+            } => {
+                let mut write = environment.data.write().unwrap();
+                if !write.options.options.is_empty() && !write.more_pages() {
+                    if let KeyEvent {
+                        code: KeyCode::Down,
+                        ..
+                    } = key
+                    {
+                        let len = write.options.options.len();
+                        if len != 0 && write.options.selected.selected().is_none() {
+                            write.options.selected.select(Some(0));
+                        } else if let Some(v) = write.options.selected.selected() {
+                            write.options.selected.select(Some((v + len + 1) % len));
+                        }
+                    } else {
+                        let len = write.options.options.len();
+                        if let Some(v) = write.options.selected.selected() {
+                            write.options.selected.select(Some((v + len - 1) % len));
                         }
                     }
                 }
+            }
 
-                KeyEvent {
-                    code: KeyCode::Enter,
-                    kind: KeyEventKind::Press,
-                    ..
-                } => {
-                    let selected = {
-                        let read = environment.data.read().unwrap();
-                        read.options.selected.selected().is_some()
-                    };
-                    if selected {
-                        let option = {
-                            let read = environment.data.read().unwrap();
-                            let option = read.options.options
-                                [read.options.selected.selected().unwrap()]
-                            .clone();
-                            option
-                        };
-
-                        let _ = eval(
-                            Rc::clone(&environment.context),
-                            &lisp! {
-                                ( {option.action} )
-                            },
-                        )
-                        .expect("Failed to evaluate lisp block");
-                        // TODO(silky): proper error handling instead of just .expect-ing everything
-                    }
-                }
-                _ => {}
-            };
-            match key {
-                KeyEvent {
-                    code: KeyCode::Char(c),
-                    kind: KeyEventKind::Press,
-                    ..
-                } => {
-                    let listeners = environment.data.read().unwrap().listeners.clone();
-                    for (_, cb) in listeners.keyboard_char {
-                        eval(
-                            Rc::clone(&environment.context),
-                            &lisp! {
-                                ( {cb} {Value::String(c.to_string())} )
-                            },
-                        )
-                        .expect("Failed to evaluate expression in keyboard_char listener");
-                    }
+            KeyEvent {
+                code: KeyCode::Enter,
+                kind: KeyEventKind::Press,
+                ..
+            } => {
+                let selected = {
+                    let read = environment.data.read().unwrap();
+                    read.options
+                        .selected
+                        .selected()
+                        .filter(|_| !read.more_pages())
+                };
+                if let Some(selected) = selected {
+                    environment.select_option(selected)?;
                 }
-                _ => {}
             }
+            _ => {}
+        };
+        if let KeyEvent {
+            code: KeyCode::Char(c),
+            kind: KeyEventKind::Press,
+            ..
+        } = key
+        {
+            environment.key_char(c)?;
         }
-        _ => {}
     }
     Ok(())
 }
@@ -370,7 +402,15 @@ fn story_render(frame: &mut Frame, environment: &mut Environment) -> Result<()>
         .direction(Direction::Horizontal)
         .constraints(vec![Constraint::Fill(1), Constraint::Fill(4)])
         .split(layout_vert[1]);
-    let line = data.display.content.to_line();
+    let now = data.display.clock;
+    let mut line = data.display.content.to_animated_line(now);
+    if data.display.wait_key && (now / 500).is_multiple_of(2) {
+        let indicator = Span::from(" ▼").fg(data.display.display_ac.to_ratatui_color());
+        match line.last_mut() {
+            Some(last) => last.push_span(indicator),
+            None => line.push(Line::from(indicator)),
+        }
+    }
     let scroll = data.display.scroll as usize;
     let scroll = scroll.clamp(0, line.len());
     let displays = Paragraph::new(Text::from(line[scroll..].to_vec())).wrap(Wrap { trim: false });
@@ -384,12 +424,15 @@ fn story_render(frame: &mut Frame, environment: &mut Environment) -> Result<()>
                 .bg(data.display.display_bg.to_ratatui_color()),
         );
 
-    let buttons: Vec<Text> = data
-        .options
-        .options
-        .iter()
-        .map(|v| v.name.to_text())
-        .collect();
+    let buttons: Vec<Text> = match data.more_pages() {
+        true => vec![],
+        false => data
+            .options
+            .options
+            .iter()
+            .map(|v| v.name.to_text())
+            .collect(),
+    };
     let buttons_bar = List::new(buttons)
         .direction(ListDirection::TopToBottom)
         .highlight_symbol("<> ")
@@ -431,7 +474,7 @@ fn story_render(frame: &mut Frame, environment: &mut Environment) -> Result<()>
             .iter()
             .rev()
             .take(3)
-            .map(|v| v.clone())
+            .cloned()
             .collect::<Vec<String>>()
             .join("\n"),
     );
@@ -440,64 +483,55 @@ fn story_render(frame: &mut Frame, environment: &mut Environment) -> Result<()>
     Ok(())
 }
 
-fn folders_render(
-    frame: &mut Frame<'_>,
-    _state: &mut TuiState,
-    clone: &mut ListState,
-    projects: &Vec<ProjectDetails>,
-) -> Result<()> {
-    let block = Block::bordered()
-        .border_set(border::ROUNDED)
-        .title("Projects");
-    if projects.is_empty() {
-        let widget = Paragraph::new(
-            "Either I couldn't find the stories/ folder\nor the folder was empty :(",
-        );
-        frame.render_widget(widget.block(block), frame.area());
-    } else {
-        let list = List::new(
-            projects
-                .iter()
-                .map(|v| {
-                    if v.author.is_some() {
-                        format!("{} - {}", v.name, v.author.clone().unwrap())
-                    } else {
-                        v.name.clone()
-                    }
-                })
-                .collect::<Vec<String>>(),
-        )
-        .block(block)
-        .direction(ListDirection::TopToBottom)
-        .highlight_style(Style::default().bold())
-        .highlight_symbol("> ");
-        frame.render_stateful_widget(list, frame.area(), clone);
-    }
-    Ok(())
-}
-
-fn cli(subc: Action) -> Result<()> {
+fn cli(subc: Action, dev: bool, library: &[String], speed: Option<f64>) -> Result<()> {
     match subc {
-        Action::Run { project_root } => {
+        Action::Run {
+            project_root,
+            room,
+            set,
+        } => {
             let mut parser = ProjectParser::new(&project_root);
             let project = parser.parse()?;
 
-            let environment = Environment::new().register_all();
-
-            let room = project
-                .rooms
-                .get(&project.meta.settings.first_room)
-                .unwrap();
-            let p = parse(&room.pre);
-            for roots in p {
-                let e = eval(Rc::clone(&environment.context), &roots.unwrap());
-                match e {
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!("error: {}", e.msg);
-                    }
+            let mut environment = Environment::new().register_all();
+            environment.speed = reading_speed(speed)?;
+            let room = room.unwrap_or(project.meta.settings.first_room.clone());
+            environment.data.write().unwrap().project = project;
+
+            let variables = environment.eval_assignments(&set)?;
+            environment.start_at(&room, variables)?;
+            tui(TuiState::Story, environment, dev, &library_roots(library)?)?;
+        }
+        Action::ServeStdio {
+            project_root,
+            ticks,
+        } => stdio::serve_stdio(&project_root, ticks, reading_speed(speed)?)?,
+        Action::Serve {
+            project_root,
+            port,
+            bind,
+        } => server::serve(&project_root, &bind, port, reading_speed(speed)?)?,
+        Action::Repl { project_root } => repl::repl(project_root.as_deref())?,
+        Action::Pack {
+            project_root,
+            output,
+        } => {
+            let output = match output {
+                Some(v) => PathBuf::from(v),
+                None => {
+                    let folder = std::fs::canonicalize(&project_root)?;
+                    let name = folder.file_name().unwrap_or_default().to_string_lossy();
+                    PathBuf::from(format!("{}.{}", name, archive::ARCHIVE_EXTENSION))
                 }
-            }
+            };
+            let manifest = archive::pack(&project_root, &output)?;
+            println!(
+                "Packed {} ({} files) into {}\nchecksum {}",
+                manifest.name,
+                manifest.files.len(),
+                output.display(),
+                manifest.checksum
+            );
         }
     }
     Ok(())
@@ -1,38 +1,230 @@
 use crate::{
+    archive::ProjectSource,
     errors::HHEError,
-    project::{Content, Project, Room},
+    lisp::{character::Character, color::Color, style::Style},
+    project::{Content, Markup, Project, Room, RoomMeta},
 };
 use anyhow::Result;
+use rust_lisp::model::Value;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+};
 
+/// Loads a project from a folder or a `.hhe` archive.
 pub struct ProjectParser {
     root: PathBuf,
+    source: ProjectSource,
     metadata: Metadata,
+    /// File each room id came from, to report duplicates
+    room_files: HashMap<String, PathBuf>,
 }
 
+/// Features a story can ask for with `features = [...]` in `[settings]`.
+pub const ENGINE_FEATURES: &[&str] = &["libs", "archives", "saves"];
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Metadata {
     pub settings: MetadataSettings,
     pub meta: MetadataInfo,
+    /// Named styles for `(style 'name)` and option labels
+    #[serde(default)]
+    pub styles: BTreeMap<String, Style>,
+    /// Speakers for `@id: text` dialogue lines, keyed by id
+    #[serde(default)]
+    pub characters: BTreeMap<String, Character>,
 }
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct MetadataSettings {
     pub first_room: String,
     pub rooms_folder: Vec<String>,
+    #[serde(default = "default_libs_folder")]
+    pub libs_folder: Vec<String>,
+    /// Turns on developer tools like the console, same as `--dev`
+    #[serde(default)]
+    pub dev: bool,
+    /// Only files with these extensions in the rooms folders are rooms
+    #[serde(default = "default_room_extensions")]
+    pub room_extensions: Vec<String>,
+    /// Globs for files in the rooms folders that aren't rooms, see `glob_match`
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Oldest hhe3 version that can play the story, e.g. "0.2.0"
+    #[serde(default)]
+    pub min_engine_version: Option<String>,
+    /// Engine features the story relies on, see `ENGINE_FEATURES`
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Reads `**bold**`, `*italic*`, `~~crossed~~`, `__underline__` and
+    /// `[color=red]...[/color]` in room content
+    #[serde(default)]
+    pub markup: bool,
+}
+
+fn default_libs_folder() -> Vec<String> {
+    vec!["./libs".to_string()]
+}
+
+fn default_room_extensions() -> Vec<String> {
+    vec!["hh3".to_string()]
+}
+
+/// Matches `path` against `pattern`, where `*` is any run of characters
+/// other than `/`, `**` is any run including `/` and `?` is one character.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(p: &[char], s: &[char]) -> bool {
+        match p {
+            [] => s.is_empty(),
+            ['*', '*', rest @ ..] => {
+                (0..=s.len()).any(|i| matches(rest, &s[i..]))
+                    || matches!(rest, ['/', ..]) && matches(&rest[1..], s)
+            }
+            ['*', rest @ ..] => (0..=s.len())
+                .take_while(|i| *i == 0 || s[i - 1] != '/')
+                .any(|i| matches(rest, &s[i..])),
+            ['?', rest @ ..] => matches!(s, [c, ..] if *c != '/') && matches(rest, &s[1..]),
+            [c, rest @ ..] => s.first() == Some(c) && matches(rest, &s[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches(&pattern, &path)
+}
+
+/// Joins the normal parts of `path` with `/`, dropping any `.`.
+pub fn path_key(path: &Path) -> String {
+    path.components()
+        .filter(|v| !matches!(v, std::path::Component::CurDir))
+        .map(|v| v.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/")
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct MetadataInfo {
     pub author: Option<String>,
     pub name: String,
+    /// A blurb shown in the story picker
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Rough time to play through, e.g. "20 minutes"
+    #[serde(default)]
+    pub length: Option<String>,
+    #[serde(default)]
+    pub content_warnings: Vec<String>,
+    /// Text file inside the project holding ASCII art for the picker
+    #[serde(default)]
+    pub cover: Option<String>,
+}
+
+fn parse_version(version: &str) -> Result<Vec<u64>> {
+    version
+        .trim()
+        .split('.')
+        .map(|v| {
+            v.parse::<u64>()
+                .map_err(|_| HHEError::BadVersion(version.to_string()).into())
+        })
+        .collect()
+}
+
+/// Compares two dotted versions part by part, with missing parts counting as
+/// zero so "1.0" and "1.0.0" are the same version.
+fn compare_versions(a: &str, b: &str) -> Result<Ordering> {
+    let (a, b) = (parse_version(a)?, parse_version(b)?);
+    let part = |v: &[u64], i: usize| v.get(i).copied().unwrap_or(0);
+    Ok((0..a.len().max(b.len()))
+        .map(|i| part(&a, i).cmp(&part(&b, i)))
+        .find(|v| v.is_ne())
+        .unwrap_or(Ordering::Equal))
+}
+
+/// Number of single character edits to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            row.push((prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+/// For serde's "unknown field `x`, expected one of `y`, `z`" errors, the
+/// expected field closest to what was written, if any is close.
+fn field_suggestion(message: &str) -> Option<String> {
+    let rest = message.strip_prefix("unknown field `")?;
+    let field = rest.split('`').next().unwrap_or_default();
+    let expected = rest
+        .split_once(", expected")
+        .map(|v| v.1)
+        .unwrap_or_default();
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|v| (edit_distance(field, v), v))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, v)| v.to_string())
+}
+
+fn suggest_field(error: toml::de::Error) -> anyhow::Error {
+    match field_suggestion(error.message()) {
+        Some(v) => HHEError::UnknownField(error.to_string(), v).into(),
+        None => error.into(),
+    }
+}
+
+impl Metadata {
+    /// Parses a `meta.toml`, checking that this engine can play the story.
+    pub fn from_toml(content: &str) -> Result<Metadata> {
+        let meta: Metadata = toml::from_str(content).map_err(suggest_field)?;
+
+        if let Some(required) = &meta.settings.min_engine_version {
+            let current = env!("CARGO_PKG_VERSION");
+            if compare_versions(required, current)?.is_gt() {
+                return Err(HHEError::EngineTooOld(required.clone(), current.to_string()).into());
+            }
+        }
+
+        let missing: Vec<&str> = meta
+            .settings
+            .features
+            .iter()
+            .filter(|v| !ENGINE_FEATURES.contains(&v.as_str()))
+            .map(|v| v.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(HHEError::MissingFeatures(missing.join(", ")).into());
+        }
+        Ok(meta)
+    }
 }
 
 impl ProjectParser {
     pub fn new(folder_path: &str) -> ProjectParser {
         ProjectParser {
             root: PathBuf::from(folder_path),
+            source: ProjectSource::Folder(PathBuf::from(folder_path)),
             metadata: Metadata::default(),
+            room_files: HashMap::new(),
         }
     }
 
@@ -48,13 +240,15 @@ impl ProjectParser {
             }
         };
 
-        let mut meta_toml = self.root.clone();
-        meta_toml.push("meta.toml");
-        let meta_content = fs::read_to_string(meta_toml)?;
-        let meta: Metadata = toml::from_str(&meta_content)?;
+        self.source = ProjectSource::open(&self.root)?;
+
+        let meta_content = self.source.read_to_string(Path::new("meta.toml"))?;
+        let meta = Metadata::from_toml(&meta_content)?;
         self.metadata = meta;
         let mut proj = Project {
+            root: self.root.clone(),
             rooms: HashMap::new(),
+            libs: HashMap::new(),
             name: self.metadata.meta.name.clone(),
             meta: self.metadata.clone(),
             author: self
@@ -65,117 +259,892 @@ impl ProjectParser {
                 .unwrap_or("No author provided".into()),
         };
 
-        self.parse_rooms(&mut proj);
+        self.parse_rooms(&mut proj)?;
+        self.parse_libs(&mut proj)?;
 
         Ok(proj)
     }
 
-    pub fn parse_rooms(&mut self, proj: &mut Project) {
+    /// Reads every file in the libs folders so rooms can `import` them by file stem.
+    /// Missing libs folders are skipped.
+    pub fn parse_libs(&mut self, proj: &mut Project) -> Result<()> {
+        for fold in self.metadata.settings.libs_folder.clone() {
+            let path = PathBuf::from(fold);
+            if !self.source.is_dir(&path) {
+                continue;
+            }
+            for path in self.source.files_under(&path)? {
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                proj.libs.insert(name, self.source.read_to_string(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse_rooms(&mut self, proj: &mut Project) -> Result<()> {
         for fold in self.metadata.settings.rooms_folder.clone() {
-            self.parse_rooms_folder(proj, &self.root.clone().join(fold));
+            self.parse_rooms_folder(proj, &PathBuf::from(fold))?;
         }
+        Ok(())
     }
-    pub fn parse_rooms_folder(&mut self, proj: &mut Project, folder_path: &PathBuf) {
-        let dir = fs::read_dir(folder_path).unwrap();
-        for fold in dir {
-            let fold = fold.unwrap();
-            let meta = fs::metadata(fold.path()).unwrap();
-            if meta.is_dir() {
-                self.parse_rooms_folder(proj, &fold.path());
+    /// Whether the file at `relative`, a path inside a rooms folder, is a room.
+    fn is_room(&self, relative: &Path) -> bool {
+        let settings = &self.metadata.settings;
+        let extension = relative
+            .extension()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !settings.room_extensions.contains(&extension) {
+            return false;
+        }
+        let key = path_key(relative);
+        let file_name = relative
+            .file_name()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
+        !settings.ignore.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob_match(pattern, &key)
             } else {
-                let room = self.parse_room(fold.path());
-                proj.rooms.insert(
-                    fold.path()
-                        .file_stem()
-                        .unwrap()
-                        .to_os_string()
-                        .into_string()
-                        .unwrap(),
-                    room,
-                );
-            }
-        }
-    }
-    pub fn parse_room(&mut self, folder_path: PathBuf) -> Room {
-        let file = fs::read_to_string(folder_path).expect("Failed to open file");
-        let mut pre = String::new();
-        let mut post = String::new();
-
-        let mut output: Vec<Content> = vec![];
-        let mut pre_or_post = 0;
-
-        let lines = file.lines();
-        let mut buffer = "".to_string();
-
-        for ln in lines {
-            if ln.trim_end() == "---" {
-                match pre_or_post {
-                    0 => {
-                        pre = buffer.trim().to_string();
-                    }
-                    1 => {
-                        output = parse_content(buffer.trim());
-                    }
-                    2 => {
-                        post = buffer.trim().to_string();
-                    }
-                    _ => {}
-                };
-                buffer = "".into();
-                pre_or_post += 1;
+                glob_match(pattern, &file_name)
+            }
+        })
+    }
+
+    /// Parses every room below `folder_path`, which is relative to the project
+    /// root. Ids are the path inside the folder without the extension, e.g. `ch1/start`.
+    pub fn parse_rooms_folder(&mut self, proj: &mut Project, folder_path: &Path) -> Result<()> {
+        let folder = PathBuf::from(path_key(folder_path));
+        for path in self.source.files_under(folder_path)? {
+            let relative = path.strip_prefix(&folder)?.to_path_buf();
+            if !self.is_room(&relative) {
+                continue;
+            }
+            let id = path_key(&relative.with_extension(""));
+            if let Some(other) = self.room_files.get(&id) {
+                return Err(HHEError::DuplicateRoom(
+                    id,
+                    other.display().to_string(),
+                    path.display().to_string(),
+                )
+                .into());
+            }
+            let room = self.parse_room(&path)?;
+            proj.rooms.insert(id.clone(), room);
+            self.room_files.insert(id, path);
+        }
+        Ok(())
+    }
+    pub fn parse_room(&mut self, path: &Path) -> Result<Room> {
+        let source = self.source.read_to_string(path)?;
+        parse_room_source(
+            &path.display().to_string(),
+            &source,
+            self.metadata.settings.markup,
+        )
+    }
+}
+
+const SECTIONS: &[&str] = &["pre", "content", "post", "exit"];
+
+/// Builds a `RoomSyntax` error for line `line` (1 based) of `file`.
+fn syntax_error(file: &str, line: usize, message: impl Into<String>) -> anyhow::Error {
+    HHEError::RoomSyntax(file.to_string(), line, message.into()).into()
+}
+
+/// The name in a `--- name` section header, if `line` is one.
+fn section_header(line: &str) -> Option<&str> {
+    let name = line.trim_end().strip_prefix("--- ")?.trim();
+    (!name.is_empty() && !name.contains(char::is_whitespace)).then_some(name)
+}
+
+/// Un-escapes a `\---` line, which stands for a literal `---`.
+fn unescape_line(line: &str) -> &str {
+    match line.strip_prefix('\\') {
+        Some(rest) if rest.starts_with("---") => rest,
+        _ => line,
+    }
+}
+
+/// Whether `source` is only lisp forms, rather than prose.
+fn is_lisp_only(source: &str) -> bool {
+    rust_lisp::parser::parse(source).all(|v| matches!(v, Ok(Value::List(_))))
+}
+
+/// Checks that a lisp section parses, so mistakes show up when the story loads.
+fn check_lisp(file: &str, line: usize, source: &str) -> Result<()> {
+    for root in rust_lisp::parser::parse(source) {
+        if let Err(e) = root {
+            return Err(syntax_error(file, line, e.msg));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a room file. A room is optional TOML front matter between `+++`
+/// lines followed by named sections:
+///
+/// ```text
+/// +++
+/// title = "Hallway"
+/// +++
+/// --- pre
+/// (delay/set 10)
+/// --- content
+/// Text with `(lisp)` in backticks. \` is a literal backtick.
+/// ---
+/// A bare --- is a scene break, \--- at the start of a line is a literal ---
+/// --- post
+/// --- exit
+/// ```
+///
+/// Files without any `--- name` header use the old layout, where bare `---`
+/// lines split the pre, content and post sections. Pre and post must be lisp
+/// there, so a scene break can't silently turn content into post.
+///
+/// With `markup` the content can also use markup for styles, see [`Markup`].
+pub fn parse_room_source(file: &str, source: &str, markup: bool) -> Result<Room> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut index = 0;
+
+    let mut meta = RoomMeta::default();
+    if lines.first().map(|v| v.trim_end()) == Some("+++") {
+        let end = lines
+            .iter()
+            .skip(1)
+            .position(|v| v.trim_end() == "+++")
+            .map(|v| v + 1)
+            .ok_or(syntax_error(
+                file,
+                1,
+                "front matter isn't closed with `+++`",
+            ))?;
+        let toml_source = lines[1..end].join("\n");
+        meta = toml::from_str::<RoomMeta>(&toml_source).map_err(|e| {
+            let offset = e.span().map(|v| v.start).unwrap_or(0);
+            let line = 2 + toml_source[..offset].matches('\n').count();
+            let mut message = match e.message().trim() {
+                "" => "invalid TOML".to_string(),
+                v => v.replace('\n', ", "),
+            };
+            if let Some(field) = field_suggestion(e.message()) {
+                message = format!("{}, did you mean `{}`?", message, field);
+            }
+            syntax_error(file, line, format!("in front matter: {}", message))
+        })?;
+        index = end + 1;
+    }
+
+    // (name, line of the first line in the section, text)
+    let mut sections: Vec<(String, usize, String)> = vec![];
+    let named = lines[index..].iter().any(|v| section_header(v).is_some());
+    if named {
+        for (i, line) in lines.iter().enumerate().skip(index) {
+            if let Some(name) = section_header(line) {
+                if !SECTIONS.contains(&name) {
+                    return Err(syntax_error(
+                        file,
+                        i + 1,
+                        format!(
+                            "unknown section `{}`, expected one of {}",
+                            name,
+                            SECTIONS.join(", ")
+                        ),
+                    ));
+                }
+                if sections.iter().any(|v| v.0 == name) {
+                    return Err(syntax_error(
+                        file,
+                        i + 1,
+                        format!("the {} section appears twice", name),
+                    ));
+                }
+                sections.push((name.to_string(), i + 2, String::new()));
+            } else if let Some(section) = sections.last_mut() {
+                section.2.push_str(unescape_line(line));
+                section.2.push('\n');
+            } else if !line.trim().is_empty() {
+                return Err(syntax_error(
+                    file,
+                    i + 1,
+                    "text before the first section, start one with e.g. `--- content`",
+                ));
+            }
+        }
+    } else {
+        let mut start = index + 1;
+        let mut buffer = String::new();
+        for (i, line) in lines.iter().enumerate().skip(index) {
+            if line.trim_end() == "---" {
+                if sections.len() == 2 {
+                    return Err(syntax_error(
+                        file,
+                        i + 1,
+                        "a room without named sections only has pre, content and post. \
+                         Use `--- pre`, `--- content` and `--- post` headers, or write \\--- for a literal ---",
+                    ));
+                }
+                sections.push((SECTIONS[sections.len()].to_string(), start, buffer));
+                buffer = String::new();
+                start = i + 2;
             } else {
-                buffer.push_str(&(ln.to_string() + "\n"));
+                buffer.push_str(unescape_line(line));
+                buffer.push('\n');
             }
         }
-        match pre_or_post {
-            0 => {
-                pre = buffer;
+        sections.push((SECTIONS[sections.len()].to_string(), start, buffer));
+        // A bare --- is also how people write scene breaks, so only trust it as
+        // a separator when the text it splits off is lisp.
+        for (i, (name, _, text)) in sections.iter().enumerate() {
+            if sections.len() > 1 && name != "content" && !is_lisp_only(text) {
+                let line = sections[i.max(1)].1 - 1;
+                return Err(syntax_error(
+                    file,
+                    line,
+                    format!(
+                        "this --- makes the text {} it the {} section, but that text isn't lisp. \
+                         Use `--- pre`, `--- content` and `--- post` headers, or write \\--- for a literal ---",
+                        if i == 0 { "before" } else { "after" },
+                        name
+                    ),
+                ));
             }
-            1 => {
-                output = parse_content(&buffer.trim());
+        }
+    }
+
+    let mut room = Room {
+        pre: String::new(),
+        post: String::new(),
+        exit: String::new(),
+        content: vec![],
+        meta,
+    };
+    for (name, line, text) in sections {
+        match name.as_str() {
+            "content" => room.content = parse_content(file, line, &text, markup)?,
+            _ => {
+                check_lisp(file, line, &text)?;
+                let text = text.trim().to_string();
+                match name.as_str() {
+                    "pre" => room.pre = text,
+                    "post" => room.post = text,
+                    _ => room.exit = text,
+                }
             }
-            2 => {
-                post = buffer;
+        }
+    }
+    Ok(room)
+}
+
+/// An `if` block in content that hasn't reached its `end` yet.
+struct Block {
+    line: usize,
+    branches: Vec<(String, Vec<Content>)>,
+    otherwise: Option<Vec<Content>>,
+    /// Markup open when the block started, which every branch starts from
+    markup: OpenMarkup,
+}
+
+impl Block {
+    fn current(&mut self) -> &mut Vec<Content> {
+        match &mut self.otherwise {
+            Some(v) => v,
+            None => &mut self.branches.last_mut().unwrap().1,
+        }
+    }
+}
+
+/// Where content goes: the innermost open block, or the top level.
+fn target<'a>(content: &'a mut Vec<Content>, blocks: &'a mut [Block]) -> &'a mut Vec<Content> {
+    match blocks.last_mut() {
+        Some(block) => block.current(),
+        None => content,
+    }
+}
+
+/// Handles one backticked piece of content. `if cond`, `elif cond`, `else`
+/// and `end` open and close blocks, and `page` breaks the page; these return
+/// true. Anything else is lisp.
+///
+/// Only one branch of an `if` is typed out, so markup has to be closed in the
+/// branch it was opened in, and each branch starts with the markup that was
+/// open at the `if`.
+fn content_tag(
+    file: &str,
+    line: usize,
+    source: String,
+    content: &mut Vec<Content>,
+    blocks: &mut Vec<Block>,
+    open_markup: &mut OpenMarkup,
+) -> Result<bool> {
+    let trimmed = source.trim();
+    let (word, rest) = trimmed
+        .split_once(char::is_whitespace)
+        .map(|(word, rest)| (word, rest.trim()))
+        .unwrap_or((trimmed, ""));
+    let condition = |rest: &str| -> Result<String> {
+        if rest.is_empty() {
+            return Err(syntax_error(
+                file,
+                line,
+                format!("`{}` needs a condition", word),
+            ));
+        }
+        check_lisp(file, line, rest)?;
+        Ok(rest.to_string())
+    };
+    let open = |blocks: &mut Vec<Block>, open_markup: &mut OpenMarkup| -> Result<()> {
+        match blocks.last() {
+            Some(block) => {
+                open_markup.check_branch(file, line, word, &block.markup)?;
+                *open_markup = block.markup.clone();
+                Ok(())
             }
-            _ => {}
-        };
+            None => Err(syntax_error(
+                file,
+                line,
+                format!("`{}` without an `if`", word),
+            )),
+        }
+    };
 
-        Room {
-            pre,
-            post,
-            content: output,
+    match (word, rest.is_empty()) {
+        ("if", _) => blocks.push(Block {
+            line,
+            branches: vec![(condition(rest)?, vec![])],
+            otherwise: None,
+            markup: open_markup.clone(),
+        }),
+        ("elif", _) => {
+            open(blocks, open_markup)?;
+            let condition = condition(rest)?;
+            let block = blocks.last_mut().unwrap();
+            if block.otherwise.is_some() {
+                return Err(syntax_error(file, line, "`elif` after `else`"));
+            }
+            block.branches.push((condition, vec![]));
+        }
+        ("else", true) => {
+            open(blocks, open_markup)?;
+            let block = blocks.last_mut().unwrap();
+            if block.otherwise.is_some() {
+                return Err(syntax_error(file, line, "this `if` already has an `else`"));
+            }
+            block.otherwise = Some(vec![]);
+        }
+        ("page", true) => target(content, blocks).push(Content::PageBreak),
+        ("end", true) => {
+            open(blocks, open_markup)?;
+            let block = blocks.pop().unwrap();
+            target(content, blocks).push(Content::If {
+                branches: block.branches,
+                otherwise: block.otherwise.unwrap_or_default(),
+            });
+        }
+        _ => {
+            let item = match source.strip_prefix('=') {
+                Some(expr) => {
+                    check_lisp(file, line, expr)?;
+                    Content::Interpolate(expr.to_string())
+                }
+                None => {
+                    check_lisp(file, line, &source)?;
+                    Content::Lisp(source)
+                }
+            };
+            target(content, blocks).push(item);
+            return Ok(false);
         }
     }
+    Ok(true)
 }
 
-fn parse_content(buf: &str) -> Vec<Content> {
-    let mut in_lisp = false;
-    let mut content = vec![];
+/// Markup styles opened in content but not closed yet, with the line each
+/// was opened on.
+#[derive(Clone, Default)]
+struct OpenMarkup {
+    bold: Option<usize>,
+    italic: Option<usize>,
+    crossed: Option<usize>,
+    underline: Option<usize>,
+    colors: Vec<usize>,
+}
 
-    let mut buffer = String::new();
+impl OpenMarkup {
+    /// Each toggled style with the line it was opened on, if it's open.
+    fn toggles(&self) -> [(Option<usize>, &'static str); 4] {
+        [
+            (self.bold, "**"),
+            (self.italic, "*"),
+            (self.crossed, "~~"),
+            (self.underline, "__"),
+        ]
+    }
+
+    /// Checks that the `if` branch ending at `line` with `word` left the same
+    /// markup open as `start`, the markup open when the `if` started.
+    fn check_branch(&self, file: &str, line: usize, word: &str, start: &OpenMarkup) -> Result<()> {
+        let colors = (self.colors.len(), start.colors.len());
+        let opened = self
+            .toggles()
+            .into_iter()
+            .zip(start.toggles())
+            .find_map(|((now, marker), (before, _))| match (now, before) {
+                (Some(v), None) => Some(Ok((v, marker))),
+                (None, Some(_)) => Some(Err(marker)),
+                _ => None,
+            })
+            .or_else(|| match colors {
+                (now, before) if now > before => {
+                    Some(Ok((*self.colors.last().unwrap(), "[color=...]")))
+                }
+                (now, before) if now < before => Some(Err("[color=...]")),
+                _ => None,
+            });
+        match opened {
+            Some(Ok((start, marker))) => Err(syntax_error(
+                file,
+                start,
+                format!(
+                    "this `{}` has to be closed in its `if` branch, before the `{}`",
+                    marker, word
+                ),
+            )),
+            Some(Err(marker)) => Err(syntax_error(
+                file,
+                line,
+                format!(
+                    "the branch before this `{}` closes a `{}` opened before the `if`, \
+                     close it after the `end` instead",
+                    word, marker
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+}
 
-    for ch in buf.chars() {
-        if ch == '`' {
-            if in_lisp {
-                content.push(Content::Lisp(buffer));
+/// Reads the markup starting with `ch`, if there is any. Brackets that
+/// aren't `[color=...]` or `[/color]` are left as text.
+fn markup_token(
+    file: &str,
+    line: usize,
+    ch: char,
+    chars: &mut Peekable<Chars>,
+    open: &mut OpenMarkup,
+) -> Result<Option<Markup>> {
+    let toggle = |flag: &mut Option<usize>| {
+        let on = flag.is_none();
+        *flag = on.then_some(line);
+        on
+    };
+    let markup = match ch {
+        '*' if chars.peek() == Some(&'*') => {
+            chars.next();
+            Markup::Bold(toggle(&mut open.bold))
+        }
+        '*' => Markup::Italic(toggle(&mut open.italic)),
+        '~' if chars.peek() == Some(&'~') => {
+            chars.next();
+            Markup::Crossed(toggle(&mut open.crossed))
+        }
+        '_' if chars.peek() == Some(&'_') => {
+            chars.next();
+            Markup::Underline(toggle(&mut open.underline))
+        }
+        '[' => {
+            let tag: String = chars
+                .clone()
+                .take_while(|c| *c != ']' && *c != '\n')
+                .collect();
+            if chars.clone().nth(tag.chars().count()) != Some(']') {
+                return Ok(None);
+            }
+            let markup = if tag == "/color" {
+                if open.colors.pop().is_none() {
+                    return Err(syntax_error(
+                        file,
+                        line,
+                        "`[/color]` without a `[color=...]`",
+                    ));
+                }
+                Markup::PopFg
+            } else if let Some(name) = tag.strip_prefix("color=") {
+                let color = Color::parse(name.trim()).ok_or(syntax_error(
+                    file,
+                    line,
+                    format!("`{}` isn't a colour, use a name or #rrggbb", name.trim()),
+                ))?;
+                open.colors.push(line);
+                Markup::PushFg(color)
             } else {
-                buffer.chars().for_each(|ch| {
-                    content.push(Content::Char(ch));
-                });
+                return Ok(None);
+            };
+            for _ in 0..=tag.chars().count() {
+                chars.next();
             }
-            buffer = String::new();
-            in_lisp = !in_lisp;
-        } else {
-            buffer.push(ch);
+            markup
         }
+        _ => return Ok(None),
+    };
+    Ok(Some(markup))
+}
+
+/// Reads the `id:` after an `@` at the start of a line, and the space after it.
+fn dialogue_speaker(chars: &mut Peekable<Chars>) -> Option<String> {
+    let id: String = chars
+        .clone()
+        .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-'))
+        .collect();
+    if id.is_empty() || chars.clone().nth(id.chars().count()) != Some(':') {
+        return None;
     }
-    if in_lisp {
-        content.push(Content::Lisp(buffer));
-    } else {
-        buffer.chars().for_each(|ch| {
-            content.push(Content::Char(ch));
-        });
+    for _ in 0..=id.chars().count() {
+        chars.next();
+    }
+    if chars.peek() == Some(&' ') {
+        chars.next();
     }
+    Some(id)
+}
 
-    content
+/// Moves everything typed since a dialogue line started into its
+/// `Content::Dialogue`. `dialogue` is the line it started on, how many blocks
+/// were open then and where its content starts.
+fn close_dialogue(
+    file: &str,
+    dialogue: (usize, usize, usize),
+    content: &mut Vec<Content>,
+    blocks: &mut [Block],
+) -> Result<()> {
+    let (line, depth, start) = dialogue;
+    if blocks.len() != depth {
+        return Err(syntax_error(
+            file,
+            line,
+            "an `if` in a dialogue line has to end on the same line",
+        ));
+    }
+    let target = target(content, blocks);
+    let rest = target.split_off(start);
+    if let Some(Content::Dialogue { line, .. }) = target.last_mut() {
+        *line = rest;
+    }
+    Ok(())
+}
+
+/// Splits content into characters, backticked lisp and `if` blocks, and with
+/// `markup` also markup. `line` is the line the content starts on, used for
+/// errors. Whitespace at either end is dropped.
+fn parse_content(file: &str, line: usize, buf: &str, markup: bool) -> Result<Vec<Content>> {
+    let mut content = vec![];
+    let mut open = OpenMarkup::default();
+    let mut blocks: Vec<Block> = vec![];
+    let mut lisp: Option<(usize, String)> = None;
+    let mut line = line;
+    // whether the text so far on this line is empty, and whether it was when
+    // the current backtick opened
+    let mut line_start = true;
+    let mut tag_line_start = false;
+    // the `@speaker:` line being read, see `close_dialogue`
+    let mut dialogue: Option<(usize, usize, usize)> = None;
+
+    let mut chars = buf.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let ch = match ch {
+            '\\' if chars.peek() == Some(&'`') => chars.next().unwrap(),
+            '\\' if line_start && lisp.is_none() && chars.peek() == Some(&'@') => {
+                chars.next().unwrap()
+            }
+            '@' if line_start && lisp.is_none() => match dialogue_speaker(&mut chars) {
+                Some(speaker) => {
+                    let depth = blocks.len();
+                    let target = target(&mut content, &mut blocks);
+                    target.push(Content::Dialogue {
+                        speaker,
+                        line: vec![],
+                    });
+                    dialogue = Some((line, depth, target.len()));
+                    line_start = false;
+                    continue;
+                }
+                None => '@',
+            },
+            '\\' if markup
+                && lisp.is_none()
+                && matches!(chars.peek(), Some('*' | '~' | '_' | '[')) =>
+            {
+                let ch = chars.next().unwrap();
+                target(&mut content, &mut blocks).push(Content::Char(ch));
+                line_start = false;
+                continue;
+            }
+            ch @ ('*' | '~' | '_' | '[') if markup && lisp.is_none() => {
+                match markup_token(file, line, ch, &mut chars, &mut open)? {
+                    Some(markup) => target(&mut content, &mut blocks).push(Content::Markup(markup)),
+                    None => {
+                        target(&mut content, &mut blocks).push(Content::Char(ch));
+                        line_start = false;
+                    }
+                }
+                continue;
+            }
+            '`' => {
+                match lisp.take() {
+                    Some((start, source)) => {
+                        let tag =
+                            content_tag(file, start, source, &mut content, &mut blocks, &mut open)?;
+                        line_start = false;
+                        // a tag on a line of its own doesn't leave a blank line behind
+                        if tag && tag_line_start && chars.peek() == Some(&'\n') {
+                            chars.next();
+                            line += 1;
+                            line_start = true;
+                        }
+                    }
+                    None => {
+                        tag_line_start = line_start;
+                        lisp = Some((line, String::new()));
+                    }
+                }
+                continue;
+            }
+            ch => ch,
+        };
+        if ch == '\n' && lisp.is_none() {
+            if let Some(v) = dialogue.take() {
+                close_dialogue(file, v, &mut content, &mut blocks)?;
+            }
+        }
+        if ch == '\n' {
+            line += 1;
+        }
+        match &mut lisp {
+            Some((_, source)) => source.push(ch),
+            None => {
+                target(&mut content, &mut blocks).push(Content::Char(ch));
+                line_start = ch == '\n';
+            }
+        }
+    }
+    if let Some((start, _)) = lisp {
+        return Err(syntax_error(
+            file,
+            start,
+            "this backtick is never closed, write \\` for a literal backtick",
+        ));
+    }
+    if let Some(v) = dialogue {
+        close_dialogue(file, v, &mut content, &mut blocks)?;
+    }
+    if let Some(block) = blocks.last() {
+        return Err(syntax_error(
+            file,
+            block.line,
+            "this `if` is never closed with `end`",
+        ));
+    }
+    let mut unclosed = open.toggles().to_vec();
+    unclosed.push((open.colors.last().copied(), "[color=...]"));
+    if let Some((start, marker)) = unclosed
+        .iter()
+        .find_map(|(v, marker)| v.map(|v| (v, marker)))
+    {
+        let message = match *marker {
+            "[color=...]" => "this `[color=...]` is never closed with `[/color]`".to_string(),
+            marker => format!(
+                "this `{}` is never closed, write \\{} for a literal {}",
+                marker,
+                &marker[..1],
+                &marker[..1]
+            ),
+        };
+        return Err(syntax_error(file, start, message));
+    }
+
+    let is_space = |v: &Content| matches!(v, Content::Char(c) if c.is_whitespace());
+    let end = content.len() - content.iter().rev().take_while(|v| is_space(v)).count();
+    content.truncate(end);
+    let start = content.iter().take_while(|v| is_space(v)).count();
+    content.drain(..start);
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const META: &str =
+        "[settings]\nfirst_room = \"index\"\nrooms_folder = [\"rooms\"]\n[meta]\nname = \"t\"\n";
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("name", "name"), 0);
+        assert_eq!(edit_distance("nmae", "name"), 2);
+        assert_eq!(edit_distance("autor", "author"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggests_close_fields() {
+        let error = Metadata::from_toml(&format!("{}autor = \"me\"\n", META)).unwrap_err();
+        assert!(error.to_string().ends_with("did you mean `author`?"));
+
+        let error = Metadata::from_toml(&format!("{}zzzzzz = 1\n", META)).unwrap_err();
+        assert!(!error.to_string().contains("did you mean"));
+    }
+
+    #[test]
+    fn checks_engine_version_and_features() {
+        assert!(Metadata::from_toml(META).is_ok());
+        let too_new = META.replace("[meta]", "min_engine_version = \"999.0.0\"\n[meta]");
+        assert!(matches!(
+            Metadata::from_toml(&too_new)
+                .unwrap_err()
+                .downcast::<HHEError>(),
+            Ok(HHEError::EngineTooOld(..))
+        ));
+        let missing = META.replace("[meta]", "features = [\"libs\", \"teleport\"]\n[meta]");
+        assert_eq!(
+            Metadata::from_toml(&missing).unwrap_err().to_string(),
+            HHEError::MissingFeatures("teleport".to_string()).to_string()
+        );
+        assert!(parse_version("1.x").is_err());
+    }
+
+    #[test]
+    fn versions_of_different_lengths() {
+        let compare = |a, b| compare_versions(a, b).unwrap();
+        assert_eq!(compare("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(compare("0.1.0", "0.1"), Ordering::Equal);
+        assert_eq!(compare("1.0.1", "1.0"), Ordering::Greater);
+        assert_eq!(compare("1", "1.0.1"), Ordering::Less);
+        assert_eq!(compare("1.10", "1.9.9"), Ordering::Greater);
+        assert!(compare_versions("1.0", "1..0").is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*.md", "notes.md"));
+        assert!(!glob_match("*.md", "ch1/notes.md"));
+        assert!(glob_match("**/*.md", "ch1/notes.md"));
+        assert!(glob_match("**/*.md", "notes.md"));
+        assert!(glob_match("drafts/**", "drafts/ch1/a.hh3"));
+        assert!(glob_match("room?.hh3", "room1.hh3"));
+        assert!(!glob_match("room?.hh3", "room10.hh3"));
+        assert!(!glob_match("a?b", "a/b"));
+        assert!(!glob_match("*.md", "notes.mdx"));
+    }
+
+    #[test]
+    fn path_keys() {
+        assert_eq!(path_key(Path::new("./rooms/ch1/a.hh3")), "rooms/ch1/a.hh3");
+        assert_eq!(path_key(Path::new("rooms/./a.hh3")), "rooms/a.hh3");
+        assert_eq!(path_key(Path::new("")), "");
+    }
+
+    /// The typed out text of `content`, ignoring lisp.
+    fn text(content: &[Content]) -> String {
+        content
+            .iter()
+            .filter_map(|v| match v {
+                Content::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The line of a `RoomSyntax` error.
+    fn error_line(error: anyhow::Error) -> usize {
+        match error.downcast::<HHEError>() {
+            Ok(HHEError::RoomSyntax(_, line, _)) => line,
+            v => panic!("expected a syntax error, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn parses_named_sections() {
+        let source = "+++\ntitle = \"Hall\"\n+++\n--- pre\n(r)\n--- content\nOne\n---\nTwo\n\\---\n--- post\n(option/goto \"a\" 'a)\n";
+        let room = parse_room_source("t", source, false).unwrap();
+        assert_eq!(room.meta.title.as_deref(), Some("Hall"));
+        assert_eq!(room.pre, "(r)");
+        assert_eq!(text(&room.content), "One\n---\nTwo\n---");
+        assert_eq!(room.post, "(option/goto \"a\" 'a)");
+        assert_eq!(room.exit, "");
+    }
+
+    #[test]
+    fn rejects_bad_sections() {
+        let unknown = parse_room_source("t", "--- content\nhi\n--- middle\n", false);
+        assert_eq!(error_line(unknown.unwrap_err()), 3);
+        let twice = parse_room_source("t", "--- pre\n--- pre\n", false);
+        assert_eq!(error_line(twice.unwrap_err()), 2);
+        let stray = parse_room_source("t", "hi\n--- content\n", false);
+        assert_eq!(error_line(stray.unwrap_err()), 1);
+        let front = parse_room_source("t", "+++\ntitle = \"a\"\n", false);
+        assert_eq!(error_line(front.unwrap_err()), 1);
+    }
+
+    #[test]
+    fn parses_legacy_sections() {
+        let source = "(r)\n---\nHello\n---\n(option/goto \"a\" 'a)\n";
+        let room = parse_room_source("t", source, false).unwrap();
+        assert_eq!(room.pre, "(r)");
+        assert_eq!(text(&room.content), "Hello");
+        assert_eq!(room.post, "(option/goto \"a\" 'a)");
+
+        let room = parse_room_source("t", "---\nHello\n", false).unwrap();
+        assert_eq!(text(&room.content), "Hello");
+    }
+
+    #[test]
+    fn legacy_scene_breaks_are_errors() {
+        // Text after the second --- would silently become the post section
+        let scene_break = "(r)\n---\nOne\n---\nTwo\n";
+        let error = parse_room_source("t", scene_break, false).unwrap_err();
+        assert_eq!(error_line(error), 4);
+        // Prose before the first --- isn't a pre section either
+        let error = parse_room_source("t", "One\n---\nTwo\n", false).unwrap_err();
+        assert_eq!(error_line(error), 2);
+        let error = parse_room_source("t", "(r)\n---\nOne\n---\n(r)\n---\n", false).unwrap_err();
+        assert_eq!(error_line(error), 6);
+        // An escaped --- is content
+        let escaped = parse_room_source("t", "(r)\n---\nOne\n\\---\nTwo\n", false).unwrap();
+        assert_eq!(text(&escaped.content), "One\n---\nTwo");
+    }
+
+    #[test]
+    fn markup_stays_in_its_branch() {
+        let parse = |source: &str| parse_content("t", 1, source, true);
+        let bold = |content: &[Content]| {
+            content
+                .iter()
+                .filter(|v| matches!(v, Content::Markup(Markup::Bold(_))))
+                .count()
+        };
+
+        let content = parse("`if a`**x**`else`y`end`").unwrap();
+        let Some(Content::If {
+            branches,
+            otherwise,
+        }) = content.first()
+        else {
+            panic!("expected an if, got {:?}", content);
+        };
+        assert_eq!(bold(&branches[0].1), 2);
+        assert_eq!(bold(otherwise), 0);
+        // markup around a whole block is fine
+        assert!(parse("**a `if c`b`elif d`c`end` d**").is_ok());
+        // each branch starts with what was open at the `if`
+        assert!(parse("`if c`**b**`else`**c**`end`").is_ok());
+
+        let error = parse("`if a`\n**x\n`else`\ny**\n`end`").unwrap_err();
+        assert_eq!(error_line(error), 2);
+        let error = parse("**a\n`if c`\nb**\n`end`\n**").unwrap_err();
+        assert_eq!(error_line(error), 4);
+        let error = parse("`if c`[color=red]b`elif d`c[/color]`end`").unwrap_err();
+        assert_eq!(error_line(error), 1);
+    }
 }
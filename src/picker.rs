@@ -0,0 +1,350 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    layout::{Constraint, Direction, Layout},
+    style::{Style, Stylize},
+    symbols::border,
+    text::{Line, Text},
+    widgets::{Block, List, ListDirection, ListState, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::library::{load_progress, refresh_projects, Progress, ProjectDetails};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Name,
+    Author,
+    LastPlayed,
+}
+
+impl SortBy {
+    fn next(self) -> SortBy {
+        match self {
+            SortBy::Name => SortBy::Author,
+            SortBy::Author => SortBy::LastPlayed,
+            SortBy::LastPlayed => SortBy::Name,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortBy::Name => "name",
+            SortBy::Author => "author",
+            SortBy::LastPlayed => "last played",
+        }
+    }
+}
+
+/// What the picker wants the TUI to do after a key press.
+pub enum PickerAction {
+    None,
+    Back,
+    Open(String),
+}
+
+/// Scores how well `query` fuzzy matches `text`, lower being better. Every
+/// character of the query has to appear in order; gaps between them cost.
+fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    for q in query.to_lowercase().chars() {
+        let found = text[pos..].iter().position(|c| *c == q)?;
+        score += found;
+        pos += found + 1;
+    }
+    Some(score)
+}
+
+fn ago(secs: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or(secs);
+    let diff = now.saturating_sub(secs);
+    match diff {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} minutes ago", diff / 60),
+        3600..86400 => format!("{} hours ago", diff / 3600),
+        _ => format!("{} days ago", diff / 86400),
+    }
+}
+
+fn field(name: &str, value: String) -> Line<'static> {
+    Line::from(vec![format!("{}: ", name).bold(), value.into()])
+}
+
+/// The story list: type to search, Tab to change the order.
+#[derive(Debug, Default)]
+pub struct Picker {
+    pub projects: Vec<ProjectDetails>,
+    pub selection: ListState,
+    roots: Vec<PathBuf>,
+    progress: BTreeMap<String, Progress>,
+    query: String,
+    sort: SortBy,
+}
+
+impl Picker {
+    pub fn new(roots: &[PathBuf]) -> Picker {
+        let mut picker = Picker {
+            roots: roots.to_vec(),
+            ..Default::default()
+        };
+        picker.refresh();
+        picker
+    }
+
+    pub fn refresh(&mut self) {
+        refresh_projects(&self.roots, &mut self.projects);
+        self.progress = load_progress();
+    }
+
+    fn last_played(&self, project: &ProjectDetails) -> u64 {
+        self.progress
+            .get(&project.path)
+            .map(|v| v.last_played)
+            .unwrap_or(0)
+    }
+
+    /// Indices into `projects` matching the search, in display order.
+    pub fn visible(&self) -> Vec<usize> {
+        let mut visible: Vec<usize> = (0..self.projects.len()).collect();
+        match self.sort {
+            SortBy::Name => visible.sort_by_key(|i| self.projects[*i].info.name.to_lowercase()),
+            SortBy::Author => visible.sort_by_key(|i| {
+                let info = &self.projects[*i].info;
+                (
+                    info.author.is_none(),
+                    info.author.clone().unwrap_or_default().to_lowercase(),
+                    info.name.to_lowercase(),
+                )
+            }),
+            SortBy::LastPlayed => {
+                visible.sort_by_key(|i| std::cmp::Reverse(self.last_played(&self.projects[*i])))
+            }
+        }
+        if self.query.is_empty() {
+            return visible;
+        }
+
+        let mut scored: Vec<(usize, usize)> = visible
+            .into_iter()
+            .filter_map(|i| {
+                let info = &self.projects[i].info;
+                let haystack = [
+                    Some(info.name.as_str()),
+                    info.author.as_deref(),
+                    Some(&info.tags.join(" ")),
+                ];
+                haystack
+                    .iter()
+                    .flatten()
+                    .filter_map(|v| fuzzy_score(&self.query, v))
+                    .min()
+                    .map(|score| (i, score))
+            })
+            .collect();
+        scored.sort_by_key(|(_, score)| *score);
+        scored.into_iter().map(|(i, _)| i).collect()
+    }
+
+    pub fn selected(&self) -> Option<&ProjectDetails> {
+        let visible = self.visible();
+        let i = visible.get(self.selection.selected()?)?;
+        self.projects.get(*i)
+    }
+
+    pub fn input(&mut self, ev: &Event) -> PickerAction {
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = ev
+        else {
+            return PickerAction::None;
+        };
+        match code {
+            KeyCode::Char('r') if modifiers.contains(KeyModifiers::CONTROL) => self.refresh(),
+            KeyCode::Down => self.selection.select_next(),
+            KeyCode::Up => self.selection.select_previous(),
+            KeyCode::Tab => self.sort = self.sort.next(),
+            KeyCode::Char(c) => {
+                self.query.push(*c);
+                self.selection.select(Some(0));
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.selection.select(Some(0));
+            }
+            KeyCode::Esc if !self.query.is_empty() => self.query.clear(),
+            KeyCode::Esc => return PickerAction::Back,
+            KeyCode::Enter => {
+                if let Some(p) = self.selected() {
+                    if p.error.is_none() {
+                        return PickerAction::Open(p.path.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        PickerAction::None
+    }
+
+    /// Marks the story at `path` as broken, e.g. when its rooms fail to load.
+    pub fn set_error(&mut self, path: &str, error: String) {
+        if let Some(p) = self.projects.iter_mut().find(|v| v.path == path) {
+            p.error = Some(error);
+        }
+    }
+
+    fn details(&self, project: &ProjectDetails) -> Text<'static> {
+        let info = &project.info;
+        let mut lines: Vec<Line> = vec![];
+        if let Some(cover) = &project.cover {
+            lines.extend(cover.lines().map(|v| Line::from(v.to_string())));
+            lines.push(Line::default());
+        }
+        let mut title = info.name.clone();
+        if let Some(version) = &info.version {
+            title = format!("{} v{}", title, version);
+        }
+        lines.push(Line::from(title).bold());
+        if let Some(author) = &info.author {
+            lines.push(Line::from(format!("by {}", author)).italic());
+        }
+        lines.push(Line::default());
+        if let Some(error) = &project.error {
+            lines.extend(error.lines().map(|v| Line::from(v.to_string()).red()));
+            return Text::from(lines);
+        }
+        if let Some(description) = &info.description {
+            lines.extend(description.lines().map(|v| Line::from(v.to_string())));
+            lines.push(Line::default());
+        }
+        if let Some(length) = &info.length {
+            lines.push(field("Length", length.clone()));
+        }
+        if !info.tags.is_empty() {
+            lines.push(field("Tags", info.tags.join(", ")));
+        }
+        if !info.content_warnings.is_empty() {
+            lines.push(field("Content warnings", info.content_warnings.join(", ")).yellow());
+        }
+        match self.progress.get(&project.path) {
+            Some(progress) => {
+                lines.push(field(
+                    "Progress",
+                    format!(
+                        "{} rooms seen, last in {} ({})",
+                        progress.rooms_seen,
+                        progress.room,
+                        ago(progress.last_played)
+                    ),
+                ));
+                if !progress.endings.is_empty() {
+                    lines.push(field(
+                        "Endings reached",
+                        progress
+                            .endings
+                            .iter()
+                            .cloned()
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    ));
+                }
+            }
+            None => lines.push(field("Progress", "not played yet".to_string())),
+        }
+        Text::from(lines)
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(frame.area());
+
+        let search = if self.query.is_empty() {
+            "type to search".to_string()
+        } else {
+            format!("search: {}_", self.query)
+        };
+        let block = Block::bordered()
+            .border_set(border::ROUNDED)
+            .title(format!("Projects (sorted by {})", self.sort.label()))
+            .title_bottom(format!("{} | Tab sort, Ctrl-R refresh, Esc back", search));
+
+        if self.projects.is_empty() {
+            let roots: Vec<String> = self.roots.iter().map(|v| v.display().to_string()).collect();
+            let widget = Paragraph::new(format!(
+                "I couldn't find any stories in:\n{}\n:(",
+                roots.join("\n")
+            ));
+            frame.render_widget(widget.block(block), frame.area());
+            return;
+        }
+
+        let visible = self.visible();
+        let list = List::new(
+            visible
+                .iter()
+                .map(|i| {
+                    let v = &self.projects[*i];
+                    match (&v.error, &v.info.author) {
+                        (Some(_), _) => Text::from(format!("{} - broken", v.info.name)).red(),
+                        (None, Some(author)) => Text::from(format!("{} - {}", v.info.name, author)),
+                        (None, None) => Text::from(v.info.name.clone()),
+                    }
+                })
+                .collect::<Vec<Text>>(),
+        )
+        .block(block)
+        .direction(ListDirection::TopToBottom)
+        .highlight_style(Style::default().bold())
+        .highlight_symbol("> ");
+        frame.render_stateful_widget(list, layout[0], &mut self.selection);
+
+        let details = match self.selected() {
+            Some(project) => self.details(project),
+            None => Text::from("No story selected"),
+        };
+        frame.render_widget(
+            Paragraph::new(details).wrap(Wrap { trim: false }).block(
+                Block::bordered()
+                    .border_set(border::ROUNDED)
+                    .title("Details"),
+            ),
+            layout[1],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_matches_in_order() {
+        assert_eq!(fuzzy_score("", "Apocalypse"), Some(0));
+        assert_eq!(fuzzy_score("apo", "Apocalypse"), Some(0));
+        assert_eq!(fuzzy_score("APS", "apocalypse"), Some(6));
+        assert_eq!(fuzzy_score("spa", "apocalypse"), None);
+        assert_eq!(fuzzy_score("apocalypses", "apocalypse"), None);
+    }
+
+    #[test]
+    fn closer_matches_score_lower() {
+        let tight = fuzzy_score("hou", "house").unwrap();
+        let loose = fuzzy_score("hou", "haunted mouse").unwrap();
+        assert!(tight < loose);
+    }
+}
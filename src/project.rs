@@ -1,25 +1,189 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
-use crate::parser::Metadata;
+use serde::Deserialize;
 
+use crate::{
+    archive::ProjectSource,
+    environment::RevealMode,
+    lisp::{
+        color::{deserialize_color, Color},
+        style::Style,
+    },
+    parser::Metadata,
+};
 
 #[derive(Clone, Debug, Default)]
 pub struct Project {
+    /// Folder or archive the project was loaded from
+    pub root: PathBuf,
     pub rooms: HashMap<String, Room>,
+    /// Sources of the project's own modules, keyed by the name used with `import`
+    pub libs: HashMap<String, String>,
     pub author: String,
     pub name: String,
-    pub meta: Metadata
+    pub meta: Metadata,
+}
+
+impl Project {
+    /// Hash of every file in the project, see `archive::Manifest`. Folders are
+    /// read in full, so this is only for saving and loading.
+    pub fn checksum(&self) -> anyhow::Result<String> {
+        ProjectSource::open(&self.root)?.checksum()
+    }
+
+    /// Works out which room `id` means when used from the room `from`. Ids are
+    /// looked up next to `from` first, so `start` in `ch1/intro` is `ch1/start`,
+    /// and `..` goes up a folder. A leading `/` always starts from the top.
+    pub fn resolve_room(&self, from: &str, id: &str) -> String {
+        if let Some(id) = id.strip_prefix('/') {
+            return normalize_room(id);
+        }
+        let dir = from.rsplit_once('/').map(|v| v.0).unwrap_or("");
+        if !dir.is_empty() {
+            let relative = normalize_room(&format!("{}/{}", dir, id));
+            if self.rooms.contains_key(&relative) {
+                return relative;
+            }
+        }
+        normalize_room(id)
+    }
+}
+
+/// Removes `.` parts from a room id and applies `..` ones.
+fn normalize_room(id: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in id.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
 }
 
 #[derive(Clone, Debug)]
 pub struct Room {
     pub pre: String,
     pub post: String,
+    /// Run when the story leaves the room
+    pub exit: String,
     pub content: Vec<Content>,
+    /// The front matter between `+++` lines at the top of the room file
+    pub meta: RoomMeta,
+}
+
+/// Settings applied when a room is entered, before its pre section runs.
+/// Anything left out keeps its value from the previous room.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RoomMeta {
+    /// Shows the title bar with this text
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub title_fg: Option<Color>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub title_bg: Option<Color>,
+    /// Milliseconds between characters, like `delay/set`
+    pub delay: Option<i64>,
+    /// How content is typed out, like `delay/mode`
+    pub reveal: Option<RevealMode>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub display_fg: Option<Color>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub display_bg: Option<Color>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub display_ac: Option<Color>,
+    /// Text colours, like `fg/set` and `bg/set`
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub fg: Option<Color>,
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub bg: Option<Color>,
+    /// Free-form labels for tools, the engine doesn't use them
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Reaching this room counts as finishing the story
+    #[serde(default)]
+    pub ending: bool,
 }
 
 #[derive(Clone, Debug)]
 pub enum Content {
     Char(char),
     Lisp(String),
-}
\ No newline at end of file
+    /// `` `=expr` ``, lisp whose result is typed out as text
+    Interpolate(String),
+    /// `` `if cond` ... `elif cond` ... `else` ... `end` ``. Only the first
+    /// branch whose condition is true, or else `otherwise`, is typed out.
+    If {
+        branches: Vec<(String, Vec<Content>)>,
+        otherwise: Vec<Content>,
+    },
+    /// A style change from content markup like `**bold**`
+    Markup(Markup),
+    /// `` `page` ``, waits for a continue key and then clears the content
+    PageBreak,
+    /// An `@speaker: ...` line, typed out after the speaker's name in their
+    /// colours. `line` is the rest of the line, without the newline.
+    Dialogue {
+        speaker: String,
+        line: Vec<Content>,
+    },
+}
+
+/// Style changes written with markup instead of lisp, when `markup` is on in
+/// `[settings]`, or added around dialogue lines.
+#[derive(Clone, Debug)]
+pub enum Markup {
+    Bold(bool),
+    Italic(bool),
+    Crossed(bool),
+    Underline(bool),
+    /// `[color=...]`, the colour to use until the matching `[/color]`
+    PushFg(Color),
+    /// `[/color]`, going back to the colour before the last `[color=...]`
+    PopFg,
+    /// Saves the current style and applies this one, for dialogue. Kept
+    /// apart from `style/push`, so lisp in the line can't pop it
+    PushStyle(Style),
+    /// Goes back to the style before the last `PushStyle`
+    PopStyle,
+    /// Like `delay/set`
+    Delay(i64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_rooms_next_to_the_current_one() {
+        let mut project = Project::default();
+        for id in ["index", "start", "ch1/intro", "ch1/start"] {
+            let room = Room {
+                pre: String::new(),
+                post: String::new(),
+                exit: String::new(),
+                content: vec![],
+                meta: RoomMeta::default(),
+            };
+            project.rooms.insert(id.to_string(), room);
+        }
+        assert_eq!(project.resolve_room("ch1/intro", "start"), "ch1/start");
+        assert_eq!(project.resolve_room("ch1/intro", "/start"), "start");
+        assert_eq!(project.resolve_room("ch1/intro", "index"), "index");
+        assert_eq!(project.resolve_room("ch1/intro", "../start"), "start");
+        assert_eq!(project.resolve_room("index", "ch1/./intro"), "ch1/intro");
+        assert_eq!(project.resolve_room("index", "missing"), "missing");
+    }
+
+    #[test]
+    fn normalizes_room_ids() {
+        assert_eq!(normalize_room("a/./b"), "a/b");
+        assert_eq!(normalize_room("a/b/../c"), "a/c");
+        assert_eq!(normalize_room("../a"), "a");
+        assert_eq!(normalize_room("/a//b/"), "a/b");
+    }
+}
@@ -0,0 +1,218 @@
+use std::{path::PathBuf, rc::Rc};
+
+use anyhow::Result;
+use rust_lisp::{interpreter::eval, model::Value, parser::parse};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{environment::Environment, parser::ProjectParser};
+
+const HELP: &str = "\
+:room <id>  enter a room, running its pre section
+:reveal     type out the rest of the current room
+:help       show this message
+:quit       leave the repl";
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".hhe3_history"))
+}
+
+/// How many more `)` are needed before `source` is a complete expression.
+/// Parens inside strings and comments are ignored.
+fn open_parens(source: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut escaped = false;
+    for ch in source.chars() {
+        if in_comment {
+            in_comment = ch != '\n';
+        } else if in_string {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+        } else {
+            match ch {
+                '"' => in_string = true,
+                ';' => in_comment = true,
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+    depth
+}
+
+struct Repl {
+    environment: Environment,
+    debug_len: usize,
+}
+
+impl Repl {
+    /// Prints any debug messages pushed since the last call.
+    fn flush_debug(&mut self) {
+        let data = self.environment.data.read().unwrap();
+        for message in data.debug.iter().skip(self.debug_len) {
+            println!("debug: {}", message);
+        }
+        self.debug_len = data.debug.len();
+    }
+
+    fn print_room(&self) {
+        let data = self.environment.data.read().unwrap();
+        if !data.title.content.is_empty() {
+            println!("== {} ==", data.title.content);
+        }
+        let content = data.display.content.to_raw();
+        if !content.is_empty() {
+            println!("{}", content.trim_end());
+        }
+        for (i, option) in data.options.options.iter().enumerate() {
+            println!("[{}] {}", i, option.name.to_raw());
+        }
+    }
+
+    fn command(&mut self, line: &str) -> bool {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            ":room" => {
+                let exists = {
+                    let read = self.environment.data.read().unwrap();
+                    let room = read.project.resolve_room(&read.current_room, arg.trim());
+                    read.project.rooms.contains_key(&room)
+                };
+                if exists {
+                    let result = self.environment.enter_room(arg.trim());
+                    self.flush_debug();
+                    self.print_room();
+                    if let Err(e) = result {
+                        eprintln!("error: {}", e);
+                    }
+                } else {
+                    eprintln!("error: no room called {:?}", arg.trim());
+                }
+            }
+            ":reveal" => {
+                if self.environment.prev_room.is_empty() {
+                    eprintln!("error: not in a room, use :room first");
+                } else {
+                    let result = self.environment.reveal_all();
+                    self.flush_debug();
+                    self.print_room();
+                    if let Err(e) = result {
+                        eprintln!("error: {}", e);
+                    }
+                }
+            }
+            ":help" => println!("{}", HELP),
+            ":quit" | ":q" => return false,
+            _ => eprintln!("error: unknown command {}, try :help", command),
+        }
+        true
+    }
+
+    fn eval(&mut self, source: &str) {
+        for root in parse(source) {
+            let root = match root {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("error: {}", e.msg);
+                    return;
+                }
+            };
+            let result = eval(Rc::clone(&self.environment.context), &root);
+            self.flush_debug();
+            match result {
+                Ok(Value::String(s)) => println!("{:?}", s),
+                Ok(v) => println!("{}", v),
+                Err(e) => {
+                    eprintln!("error: {}", e.msg);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Interactive lisp prompt with every engine builtin registered. When
+/// `project_root` is given its rooms and modules are available too.
+pub fn repl(project_root: Option<&str>) -> Result<()> {
+    let environment = Environment::new().register_all();
+    if let Some(root) = project_root {
+        let project = ProjectParser::new(root).parse()?;
+        println!("Loaded {} ({} rooms)", project.name, project.rooms.len());
+        environment.data.write().unwrap().project = project;
+    }
+    println!("Type :help for commands");
+
+    let mut repl = Repl {
+        environment,
+        debug_len: 0,
+    };
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(v) => v,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            let _ = editor.add_history_entry(line.as_str());
+            if !repl.command(line.trim()) {
+                break;
+            }
+            continue;
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if open_parens(&buffer) > 0 {
+            continue;
+        }
+
+        let source = std::mem::take(&mut buffer);
+        let _ = editor.add_history_entry(source.trim());
+        repl.eval(&source);
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_unclosed_parens() {
+        assert_eq!(open_parens("(define x 1)"), 0);
+        assert_eq!(open_parens("(defun f (x)"), 1);
+        assert_eq!(open_parens("(begin (debug \"a\")\n  (if"), 2);
+        assert_eq!(open_parens("x)"), -1);
+    }
+
+    #[test]
+    fn ignores_parens_in_strings_and_comments() {
+        assert_eq!(open_parens("(debug \"(\")"), 0);
+        assert_eq!(open_parens("(debug \"\\\"(\""), 1);
+        assert_eq!(open_parens("(debug 1) ; (((\n("), 1);
+    }
+}
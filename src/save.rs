@@ -0,0 +1,124 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Result;
+use rust_lisp::model::{List, Symbol, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::environment::Environment;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SaveData {
+    pub room: String,
+    pub variables: BTreeMap<String, SaveValue>,
+    /// Checksum of the story when the save was made
+    #[serde(default)]
+    pub checksum: String,
+}
+
+/// The subset of lisp values that can be written to a save file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SaveValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    String(String),
+    Symbol { symbol: String },
+    List(Vec<SaveValue>),
+}
+
+impl SaveValue {
+    pub fn from_value(value: &Value) -> Option<SaveValue> {
+        Some(match value {
+            Value::True => SaveValue::Bool(true),
+            Value::False => SaveValue::Bool(false),
+            Value::Int(i) => SaveValue::Int(*i),
+            Value::Float(f) => SaveValue::Float(*f),
+            Value::String(s) => SaveValue::String(s.clone()),
+            Value::Symbol(s) => SaveValue::Symbol {
+                symbol: s.0.clone(),
+            },
+            Value::List(list) => SaveValue::List(
+                list.into_iter()
+                    .map(|v| SaveValue::from_value(&v))
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+            _ => return None,
+        })
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            SaveValue::Bool(true) => Value::True,
+            SaveValue::Bool(false) => Value::False,
+            SaveValue::Int(i) => Value::Int(*i),
+            SaveValue::Float(f) => Value::Float(*f),
+            SaveValue::String(s) => Value::String(s.clone()),
+            SaveValue::Symbol { symbol } => Value::Symbol(Symbol::from(symbol.as_str())),
+            SaveValue::List(values) => {
+                Value::List(values.iter().map(|v| v.to_value()).collect::<List>())
+            }
+        }
+    }
+}
+
+impl SaveData {
+    /// Snapshots the current room and every user binding that can be saved.
+    /// Functions and foreign values are skipped.
+    pub fn capture(environment: &Environment) -> SaveData {
+        let (room, checksum) = {
+            let data = environment.data.read().unwrap();
+            // left empty if the story can't be read, so it is never seen as changed
+            let checksum = data.project.checksum().unwrap_or_default();
+            (data.current_room.clone(), checksum)
+        };
+        let variables = environment
+            .user_bindings()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, SaveValue::from_value(&value)?)))
+            .collect();
+        SaveData {
+            room,
+            variables,
+            checksum,
+        }
+    }
+
+    /// Whether the story has changed since this save was made.
+    pub fn story_changed(&self, environment: &Environment) -> bool {
+        !self.checksum.is_empty()
+            && environment
+                .data
+                .read()
+                .unwrap()
+                .project
+                .checksum()
+                .is_ok_and(|v| v != self.checksum)
+    }
+
+    /// Loads the save, warning in the debug log if the story has changed since.
+    pub fn restore(&self, environment: &mut Environment) -> Result<()> {
+        if self.story_changed(environment) {
+            environment.data.write().unwrap().debug.push(
+                "warning: this save was made with a different version of the story".to_string(),
+            );
+        }
+        environment.start_at(
+            &self.room,
+            self.variables
+                .iter()
+                .map(|(k, v)| (k.clone(), v.to_value()))
+                .collect(),
+        )
+    }
+
+    pub fn read(path: &Path) -> Result<SaveData> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
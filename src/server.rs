@@ -0,0 +1,451 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use crossterm::{
+    cursor::Hide,
+    event::{Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{layout::Rect, prelude::CrosstermBackend, Terminal, TerminalOptions, Viewport};
+
+use crate::{environment::Environment, parser::ProjectParser, project::Project};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const NAWS: u8 = 31;
+
+/// How long the client has to be quiet before a pending Esc is sent as a key.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Something the client sent, after telnet negotiation has been stripped out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientInput {
+    Event(Event),
+    Resize(u16, u16),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum DecodeState {
+    #[default]
+    Data,
+    Iac,
+    Negotiate,
+    Sub,
+    SubIac,
+    Escape,
+    Csi,
+    CarriageReturn,
+}
+
+/// Turns the raw bytes of a telnet connection into key events, handling
+/// option negotiation, window size reports and ANSI escape sequences.
+#[derive(Debug, Default)]
+pub struct TelnetDecoder {
+    state: DecodeState,
+    sub: Vec<u8>,
+    utf8: Vec<u8>,
+}
+
+fn key(code: KeyCode) -> ClientInput {
+    ClientInput::Event(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)))
+}
+
+impl TelnetDecoder {
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<ClientInput> {
+        let mut out = vec![];
+        for &b in bytes {
+            self.byte(b, &mut out);
+        }
+        out
+    }
+
+    /// Sends a lone Esc the client typed, which can't be told apart from the
+    /// start of an arrow key until nothing else arrives.
+    pub fn flush(&mut self) -> Vec<ClientInput> {
+        if self.state == DecodeState::Escape {
+            self.state = DecodeState::Data;
+            vec![key(KeyCode::Esc)]
+        } else {
+            vec![]
+        }
+    }
+
+    fn byte(&mut self, b: u8, out: &mut Vec<ClientInput>) {
+        match self.state {
+            DecodeState::Iac => {
+                self.state = match b {
+                    WILL | WONT | DO | DONT => DecodeState::Negotiate,
+                    SB => {
+                        self.sub.clear();
+                        DecodeState::Sub
+                    }
+                    IAC => {
+                        // an escaped 0xFF is data, not another command
+                        self.state = DecodeState::Data;
+                        self.text(b, out);
+                        return;
+                    }
+                    _ => DecodeState::Data,
+                };
+            }
+            DecodeState::Negotiate => self.state = DecodeState::Data,
+            DecodeState::Sub => match b {
+                IAC => self.state = DecodeState::SubIac,
+                _ => self.sub.push(b),
+            },
+            DecodeState::SubIac => match b {
+                SE => {
+                    if let [NAWS, w1, w2, h1, h2] = self.sub[..] {
+                        out.push(ClientInput::Resize(
+                            u16::from_be_bytes([w1, w2]),
+                            u16::from_be_bytes([h1, h2]),
+                        ));
+                    }
+                    self.state = DecodeState::Data;
+                }
+                _ => {
+                    self.sub.push(b);
+                    self.state = DecodeState::Sub;
+                }
+            },
+            DecodeState::Escape => match b {
+                b'[' | b'O' => self.state = DecodeState::Csi,
+                _ => {
+                    out.push(key(KeyCode::Esc));
+                    self.state = DecodeState::Data;
+                    self.byte(b, out);
+                }
+            },
+            DecodeState::Csi => {
+                let code = match b {
+                    b'A' => Some(KeyCode::Up),
+                    b'B' => Some(KeyCode::Down),
+                    b'C' => Some(KeyCode::Right),
+                    b'D' => Some(KeyCode::Left),
+                    b'0'..=b'9' | b';' => return,
+                    _ => None,
+                };
+                if let Some(code) = code {
+                    out.push(key(code));
+                }
+                self.state = DecodeState::Data;
+            }
+            DecodeState::CarriageReturn => {
+                self.state = DecodeState::Data;
+                if b != b'\n' && b != 0 {
+                    self.byte(b, out);
+                }
+            }
+            DecodeState::Data => self.data(b, out),
+        }
+    }
+
+    fn data(&mut self, b: u8, out: &mut Vec<ClientInput>) {
+        match b {
+            IAC => self.state = DecodeState::Iac,
+            0x1b => self.state = DecodeState::Escape,
+            b'\r' => {
+                out.push(key(KeyCode::Enter));
+                self.state = DecodeState::CarriageReturn;
+            }
+            b'\n' => out.push(key(KeyCode::Enter)),
+            0x7f | 0x08 => out.push(key(KeyCode::Backspace)),
+            b'\t' => out.push(key(KeyCode::Tab)),
+            0x03 => out.push(ClientInput::Event(Event::Key(KeyEvent::new(
+                KeyCode::Char('c'),
+                KeyModifiers::CONTROL,
+            )))),
+            0x00..=0x1f => {}
+            _ => self.text(b, out),
+        }
+    }
+
+    /// Collects the bytes of a UTF-8 character, which may arrive split across
+    /// reads, and sends it once it is complete.
+    fn text(&mut self, b: u8, out: &mut Vec<ClientInput>) {
+        self.utf8.push(b);
+        match std::str::from_utf8(&self.utf8) {
+            Ok(s) => {
+                for ch in s.chars() {
+                    out.push(key(KeyCode::Char(ch)));
+                }
+                self.utf8.clear();
+            }
+            Err(e) if e.error_len().is_some() => self.utf8.clear(),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Plays `project` for a single client until they quit or disconnect, typing
+/// `speed` times faster than written.
+fn session(stream: TcpStream, project: Project, speed: f64) -> Result<()> {
+    let mut reader = stream.try_clone()?;
+    let mut writer = stream.try_clone()?;
+    writer.write_all(&[IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DO, NAWS])?;
+
+    reader.set_read_timeout(Some(ESCAPE_TIMEOUT))?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut decoder = TelnetDecoder::default();
+        let mut buf = [0u8; 512];
+        loop {
+            let inputs = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => decoder.decode(&buf[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    decoder.flush()
+                }
+                Err(_) => break,
+            };
+            for input in inputs {
+                if tx.send(input).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    let mut backend = CrosstermBackend::new(writer);
+    execute!(backend, EnterAlternateScreen, Hide)?;
+    let mut terminal = Terminal::with_options(
+        backend,
+        TerminalOptions {
+            viewport: Viewport::Fixed(Rect::new(0, 0, 80, 24)),
+        },
+    )?;
+    terminal.clear()?;
+
+    let mut environment = Environment::new().register_all();
+    environment.speed = speed;
+    {
+        let mut write = environment.data.write().unwrap();
+        write.current_room = project.meta.settings.first_room.clone();
+        write.project = project;
+    }
+
+    // the client's terminal is put back even if the story fails
+    let result = play(&rx, &mut terminal, &mut environment);
+    let _ = terminal.show_cursor();
+    let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen);
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    result
+}
+
+/// Runs the story loop of a session until the client quits or disconnects.
+/// Story errors are shown to the client like in the TUI, only the connection
+/// failing ends the session early.
+fn play(
+    rx: &Receiver<ClientInput>,
+    terminal: &mut Terminal<CrosstermBackend<TcpStream>>,
+    environment: &mut Environment,
+) -> Result<()> {
+    // set when the story fails, so the error is only reported once instead of
+    // every frame, until the client presses something
+    let mut stalled = false;
+    'session: loop {
+        loop {
+            match rx.try_recv() {
+                Ok(ClientInput::Resize(w, h)) => {
+                    terminal.resize(Rect::new(0, 0, w.max(1), h.max(1)))?;
+                }
+                Ok(ClientInput::Event(Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                }))) => break 'session,
+                Ok(ClientInput::Event(ev)) => {
+                    stalled = false;
+                    if let Err(e) = crate::story_input(ev, environment) {
+                        environment.report(e);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break 'session,
+            }
+        }
+
+        if !stalled {
+            if let Err(e) = environment.update() {
+                stalled = true;
+                environment.report(e);
+            }
+        }
+        if environment.data.read().unwrap().quit {
+            break;
+        }
+        let mut rendered = Ok(());
+        terminal.draw(|frame| rendered = crate::story_render(frame, environment))?;
+        if let Err(e) = rendered {
+            if !stalled {
+                stalled = true;
+                environment.report(e);
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+fn closed_by_peer(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<io::Error>().map(|e| e.kind()),
+        Some(io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset)
+    )
+}
+
+/// Accepts telnet connections on `bind:port`, giving each its own session of
+/// the project at `project_root` typed at `speed`.
+pub fn serve(project_root: &str, bind: &str, port: u16, speed: f64) -> Result<()> {
+    let mut parser = ProjectParser::new(project_root);
+    let project = parser.parse()?;
+
+    let listener = TcpListener::bind((bind, port))?;
+    eprintln!("Serving {} on {}", project.name, listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                continue;
+            }
+        };
+        let project = project.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|v| v.to_string())
+                .unwrap_or("unknown".into());
+            eprintln!("{} connected", peer);
+            match session(stream, project, speed) {
+                Err(e) if !closed_by_peer(&e) => eprintln!("{} disconnected: {}", peer, e),
+                _ => eprintln!("{} disconnected", peer),
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<ClientInput> {
+        let mut decoder = TelnetDecoder::default();
+        chunks.iter().flat_map(|v| decoder.decode(v)).collect()
+    }
+
+    #[test]
+    fn window_size_split_across_reads() {
+        let input = decode_all(&[&[IAC], &[SB, NAWS, 0, 80], &[0, 24, IAC], &[SE, b'x']]);
+        assert_eq!(
+            input,
+            [ClientInput::Resize(80, 24), key(KeyCode::Char('x'))]
+        );
+    }
+
+    #[test]
+    fn negotiation_split_across_reads() {
+        let input = decode_all(&[&[IAC, WILL], &[NAWS, b'a'], &[IAC], &[DO], &[ECHO, b'b']]);
+        assert_eq!(input, [key(KeyCode::Char('a')), key(KeyCode::Char('b'))]);
+    }
+
+    #[test]
+    fn escaped_iac_is_data() {
+        // a lone 0xFF isn't UTF-8 so it's dropped, but mustn't start a command
+        let input = decode_all(&[&[b'a', IAC], &[IAC, b'b']]);
+        assert_eq!(input, [key(KeyCode::Char('a')), key(KeyCode::Char('b'))]);
+    }
+
+    #[test]
+    fn escaped_iac_in_window_size() {
+        let input = decode_all(&[&[IAC, SB, NAWS, 0, IAC], &[IAC, 0, 24, IAC, SE]]);
+        assert_eq!(input, [ClientInput::Resize(255, 24)]);
+    }
+
+    #[test]
+    fn keys_split_across_reads() {
+        let input = decode_all(&[&[0x1b], b"[", b"A\r", b"\n", &[0xc3], &[0xa9]]);
+        assert_eq!(
+            input,
+            [
+                key(KeyCode::Up),
+                key(KeyCode::Enter),
+                key(KeyCode::Char('é'))
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_escape_is_sent_on_flush() {
+        let mut decoder = TelnetDecoder::default();
+        assert_eq!(decoder.decode(&[0x1b]), []);
+        assert_eq!(decoder.flush(), [key(KeyCode::Esc)]);
+        assert_eq!(decoder.flush(), []);
+        assert_eq!(decoder.decode(b"x"), [key(KeyCode::Char('x'))]);
+    }
+
+    #[test]
+    fn session_outlives_story_errors() {
+        let mut project = Project::default();
+        project.meta.settings.first_room = "index".to_string();
+        let source = "--- pre\n(listener/keyboard/char (lambda (c) (no-such-function)))\n";
+        let room = crate::parser::parse_room_source("index", source, false).unwrap();
+        project.rooms.insert("index".to_string(), room);
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            session(stream, project, 1.0)
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut seen = vec![];
+        let mut buf = [0u8; 4096];
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while !String::from_utf8_lossy(&seen).contains("error:") {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "the error was never shown"
+            );
+            // the listener only fails once the room has been entered, so keep
+            // pressing keys until it has
+            client.write_all(b"x").unwrap();
+            if let Ok(n) = client.read(&mut buf) {
+                assert!(n > 0, "session closed before reporting the error");
+                seen.extend_from_slice(&buf[..n]);
+            }
+        }
+
+        // still running, so Ctrl-C ends it cleanly
+        client.write_all(&[0x03]).unwrap();
+        server.join().unwrap().unwrap();
+        client.set_read_timeout(None).unwrap();
+        while client.read(&mut buf).unwrap() > 0 {}
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    io::{stdin, stdout, BufRead, Write},
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    environment::{ContentChar, Effect, Environment},
+    lisp::color::Color,
+    parser::ProjectParser,
+    save::SaveData,
+};
+
+/// Messages written to stdout, one JSON object per line.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum StdioEvent {
+    RoomEntered { room: String },
+    ContentAppended { runs: Vec<ContentRun> },
+    ContentCleared,
+    OptionsChanged { options: Vec<String> },
+    TitleChanged { title: String, show: bool },
+    WaitingForKey,
+    Debug { message: String },
+    Saved { path: String },
+    Error { message: String },
+    Quit,
+}
+
+/// Messages read from stdin, one JSON object per line.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum StdioCommand {
+    Select { option: usize },
+    Key { key: char },
+    Tick { ms: i64 },
+    Continue,
+    Save { path: String },
+    Load { path: String },
+    Quit,
+}
+
+/// A stretch of consecutive characters sharing the same style.
+#[derive(Serialize, Debug, Clone)]
+pub struct ContentRun {
+    pub text: String,
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub crossed: bool,
+    pub effect: Effect,
+}
+
+impl ContentRun {
+    fn new(ch: &ContentChar) -> ContentRun {
+        ContentRun {
+            text: ch.ch.to_string(),
+            fg: ch.fg,
+            bg: ch.bg,
+            bold: ch.bold,
+            italic: ch.italic,
+            underline: ch.underline,
+            crossed: ch.crossed,
+            effect: ch.effect,
+        }
+    }
+
+    fn same_style(&self, ch: &ContentChar) -> bool {
+        self.fg == ch.fg
+            && self.bg == ch.bg
+            && self.bold == ch.bold
+            && self.italic == ch.italic
+            && self.underline == ch.underline
+            && self.crossed == ch.crossed
+            && self.effect == ch.effect
+    }
+}
+
+pub fn to_runs(chars: &[ContentChar]) -> Vec<ContentRun> {
+    let mut runs: Vec<ContentRun> = vec![];
+    for ch in chars {
+        match runs.last_mut() {
+            Some(run) if run.same_style(ch) => run.text.push(ch.ch),
+            _ => runs.push(ContentRun::new(ch)),
+        }
+    }
+    runs
+}
+
+/// What the client has last been told, used to only emit changes.
+#[derive(Default)]
+struct Seen {
+    room: String,
+    clears: usize,
+    content_len: usize,
+    options: Vec<String>,
+    title: (String, bool),
+    waiting: bool,
+    debug_len: usize,
+}
+
+impl Seen {
+    fn diff(&mut self, environment: &Environment) -> Vec<StdioEvent> {
+        let data = environment.data.read().unwrap();
+        let mut events = vec![];
+
+        if data.current_room != self.room {
+            self.room = data.current_room.clone();
+            events.push(StdioEvent::RoomEntered {
+                room: self.room.clone(),
+            });
+        }
+
+        let content = &data.display.content.0;
+        if data.display.clears != self.clears {
+            self.clears = data.display.clears;
+            self.content_len = 0;
+            events.push(StdioEvent::ContentCleared);
+        }
+        if content.len() > self.content_len {
+            events.push(StdioEvent::ContentAppended {
+                runs: to_runs(&content[self.content_len..]),
+            });
+            self.content_len = content.len();
+        }
+
+        let options: Vec<String> = match data.more_pages() {
+            true => vec![],
+            false => data
+                .options
+                .options
+                .iter()
+                .map(|v| v.name.to_raw())
+                .collect(),
+        };
+        if options != self.options {
+            self.options = options.clone();
+            events.push(StdioEvent::OptionsChanged { options });
+        }
+
+        let title = (data.title.content.clone(), data.title.show);
+        if title != self.title {
+            self.title = title.clone();
+            events.push(StdioEvent::TitleChanged {
+                title: title.0,
+                show: title.1,
+            });
+        }
+
+        if data.display.wait_key != self.waiting {
+            self.waiting = data.display.wait_key;
+            if self.waiting {
+                events.push(StdioEvent::WaitingForKey);
+            }
+        }
+
+        for message in data.debug.iter().skip(self.debug_len) {
+            events.push(StdioEvent::Debug {
+                message: message.clone(),
+            });
+        }
+        self.debug_len = data.debug.len();
+
+        if data.quit {
+            events.push(StdioEvent::Quit);
+        }
+
+        events
+    }
+}
+
+fn emit(event: &StdioEvent) -> Result<()> {
+    let mut out = stdout().lock();
+    serde_json::to_writer(&mut out, event)?;
+    out.write_all(b"\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn apply(environment: &mut Environment, command: StdioCommand) -> Result<Option<StdioEvent>> {
+    match command {
+        StdioCommand::Select { option } => environment.select_option(option)?,
+        StdioCommand::Key { key } => environment.key_char(key)?,
+        StdioCommand::Tick { ms } => environment.step(ms)?,
+        StdioCommand::Continue => {
+            environment.continue_reading();
+        }
+        StdioCommand::Save { path } => {
+            SaveData::capture(environment).write(&PathBuf::from(&path))?;
+            return Ok(Some(StdioEvent::Saved { path }));
+        }
+        StdioCommand::Load { path } => {
+            SaveData::read(&PathBuf::from(path))?.restore(environment)?;
+        }
+        StdioCommand::Quit => environment.data.write().unwrap().quit = true,
+    }
+    Ok(None)
+}
+
+/// Plays the project at `project_root` headlessly, reporting what happens as
+/// JSON lines on stdout and taking commands as JSON lines on stdin.
+///
+/// Time follows the clock until the first `tick` command, and from then on only
+/// moves with ticks. With `ticks` set it only ever moves with ticks. Content is
+/// typed `speed` times faster than written.
+pub fn serve_stdio(project_root: &str, mut ticks: bool, speed: f64) -> Result<()> {
+    let mut parser = ProjectParser::new(project_root);
+    let project = parser.parse()?;
+
+    let mut environment = Environment::new().register_all();
+    environment.speed = speed;
+    {
+        let mut write = environment.data.write().unwrap();
+        write.current_room = project.meta.settings.first_room.clone();
+        write.project = project;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut seen = Seen::default();
+    // set when updating fails, so the error is only sent once instead of on
+    // every turn of the loop, until a command changes something
+    let mut stalled = false;
+    loop {
+        match rx.recv_timeout(Duration::from_millis(2)) {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => {
+                stalled = false;
+                let result = serde_json::from_str::<StdioCommand>(&line)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|command| {
+                        ticks |= matches!(command, StdioCommand::Tick { .. });
+                        apply(&mut environment, command)
+                    });
+                match result {
+                    Ok(Some(event)) => emit(&event)?,
+                    Ok(None) => {}
+                    Err(e) => emit(&StdioEvent::Error {
+                        message: e.to_string(),
+                    })?,
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if !stalled {
+            // with ticks, this only enters rooms and types instant content
+            let result = if ticks {
+                environment.step(0)
+            } else {
+                environment.update()
+            };
+            if let Err(e) = result {
+                stalled = true;
+                emit(&StdioEvent::Error {
+                    message: e.to_string(),
+                })?;
+            }
+        }
+        for event in seen.diff(&environment) {
+            emit(&event)?;
+        }
+        if environment.data.read().unwrap().quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str, bold: bool) -> Vec<ContentChar> {
+        text.chars()
+            .map(|ch| ContentChar {
+                ch,
+                bold,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn appended(events: &[StdioEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                StdioEvent::ContentAppended { runs } => Some(runs),
+                _ => None,
+            })
+            .flatten()
+            .map(|run| run.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn runs_split_on_style_changes() {
+        let mut content = chars("ab", false);
+        content.extend(chars("cd", true));
+        content.extend(chars("e", false));
+        let runs = to_runs(&content);
+        let texts: Vec<&str> = runs.iter().map(|v| v.text.as_str()).collect();
+        assert_eq!(texts, ["ab", "cd", "e"]);
+        assert!(runs[1].bold && !runs[2].bold);
+        assert!(to_runs(&[]).is_empty());
+    }
+
+    #[test]
+    fn clear_then_longer_content_is_sent_whole() {
+        let environment = Environment::new();
+        let mut seen = Seen::default();
+        environment.data.write().unwrap().display.content.0 = chars("old", false);
+        assert_eq!(appended(&seen.diff(&environment)), "old");
+
+        {
+            let mut data = environment.data.write().unwrap();
+            data.display.clear();
+            data.display.content.0 = chars("much longer", false);
+        }
+        let events = seen.diff(&environment);
+        assert!(matches!(events[0], StdioEvent::ContentCleared));
+        assert_eq!(appended(&events), "much longer");
+        assert!(seen.diff(&environment).is_empty());
+    }
+}